@@ -2,7 +2,7 @@
 // Example demonstrating using PgwireLite with mutual TLS (mTLS)
 
 use colorize::AnsiColor;
-use pgwire_lite::{PgwireLite, Value};
+use pgwire_lite::{ConnectionConfig, PgwireLite, SslMode, TlsConfig, Value, Verbosity};
 use std::env;
 use std::path::PathBuf;
 
@@ -69,45 +69,27 @@ fn execute_query(conn: &PgwireLite, query: &str) {
     }
 }
 
-fn setup_tls() {
-    // Set up environment variables for TLS
+fn tls_config() -> TlsConfig {
+    // Certificates generated by start-secure-server.sh
     let home_dir = env::var("HOME").expect("Could not find HOME environment variable");
     let ssl_dir = PathBuf::from(&home_dir).join("ssl-test");
 
-    // Configure TLS settings
-    env::set_var("PGSSLMODE", "verify-ca"); // Use verify-ca instead of verify-full to bypass hostname check
-    env::set_var(
-        "PGSSLCERT",
-        ssl_dir
-            .join("client_cert.pem")
-            .to_string_lossy()
-            .to_string(),
-    );
-    env::set_var(
-        "PGSSLKEY",
-        ssl_dir.join("client_key.pem").to_string_lossy().to_string(),
-    );
-    env::set_var(
-        "PGSSLROOTCERT",
-        ssl_dir
-            .join("server_cert.pem")
-            .to_string_lossy()
-            .to_string(),
-    );
-
-    // Disable hostname verification for testing purposes
-    env::set_var("PGSSLSNI", "0");
+    // Use verify-ca instead of verify-full to bypass hostname check
+    TlsConfig::new(SslMode::VerifyCa)
+        .root_cert(ssl_dir.join("server_cert.pem"))
+        .client_cert(ssl_dir.join("client_cert.pem"))
+        .client_key(ssl_dir.join("client_key.pem"))
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
-    // Setup TLS environment variables
-    setup_tls();
-
     // Create a connection configuration with TLS enabled
     // Using IP address instead of hostname to avoid certificate validation issues
-    let conn = PgwireLite::new("127.0.0.1", 5444, true, "verbose")?;
+    let config = ConnectionConfig::new("127.0.0.1", 5444)
+        .tls(tls_config())
+        .verbosity(Verbosity::Verbose);
+    let conn = PgwireLite::from_config(config)?;
 
     println!();
     println!("libpq version: {}", conn.libpq_version());
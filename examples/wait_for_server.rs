@@ -0,0 +1,28 @@
+// example/wait_for_server.rs
+// Blocks until a server accepts connections, for use by scripts that start one
+
+use pgwire_lite::PgwireLite;
+use std::env;
+use std::time::Duration;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    // Usage: wait_for_server [port] [timeout in seconds]
+    let mut args = env::args().skip(1);
+    let port = args
+        .next()
+        .map(|port| port.parse())
+        .transpose()?
+        .unwrap_or(5444);
+    let timeout = args
+        .next()
+        .map(|secs| secs.parse())
+        .transpose()?
+        .unwrap_or(30);
+
+    let conn = PgwireLite::new("localhost", port, false, "default")?;
+    conn.wait_until_ready(Duration::from_secs(timeout), Duration::from_millis(100))?;
+    println!("server on port {} is accepting connections", port);
+    Ok(())
+}
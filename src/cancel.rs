@@ -0,0 +1,363 @@
+// src/cancel.rs
+
+use std::ffi::{c_char, CStr};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::debug;
+
+use libpq::Connection;
+use libpq_sys::{PGcancel, PQcancel, PQfreeCancel, PQgetCancel};
+
+use crate::session::Session;
+
+/// The libpq cancel object of one connection.
+///
+/// A `PGcancel` is independent of the `PGconn` it was created from, so it stays
+/// valid after the connection is closed and may be used from any thread.
+pub(crate) struct CancelHandle {
+    cancel: *mut PGcancel,
+}
+
+// PQcancel is documented as safe to call from other threads
+unsafe impl Send for CancelHandle {}
+unsafe impl Sync for CancelHandle {}
+
+impl CancelHandle {
+    /// Creates the cancel object for a connection, or `None` if libpq could not.
+    pub(crate) fn new(conn: &Connection) -> Option<Self> {
+        let cancel = unsafe { PQgetCancel(conn.into()) };
+        if cancel.is_null() {
+            None
+        } else {
+            Some(CancelHandle { cancel })
+        }
+    }
+
+    /// Asks the server to cancel whatever the connection is currently executing.
+    pub(crate) fn cancel(&self) -> Result<(), String> {
+        let mut errbuf = [0 as c_char; 256];
+        let ok = unsafe { PQcancel(self.cancel, errbuf.as_mut_ptr(), errbuf.len() as i32) };
+        if ok == 1 {
+            Ok(())
+        } else {
+            let message = unsafe { CStr::from_ptr(errbuf.as_ptr()) };
+            Err(message.to_string_lossy().trim_end().to_string())
+        }
+    }
+}
+
+impl Drop for CancelHandle {
+    fn drop(&mut self) {
+        unsafe { PQfreeCancel(self.cancel) };
+    }
+}
+
+// A query currently running on a connection that tokens can cancel
+struct ActiveQuery {
+    id: u64,
+    handle: Arc<CancelHandle>,
+    cancelled: bool,
+}
+
+#[derive(Default)]
+struct ActiveQueries {
+    next_id: u64,
+    queries: Vec<ActiveQuery>,
+}
+
+/// The queries currently running for a client, shared with its cancel tokens.
+#[derive(Default)]
+pub(crate) struct CancelState {
+    active: Mutex<ActiveQueries>,
+}
+
+impl CancelState {
+    /// Runs `f` on `session` as a cancellable query.
+    ///
+    /// If a token cancelled the query and `f` failed, the error is replaced
+    /// with [`QueryCancelled`].
+    pub(crate) fn run<T>(
+        &self,
+        session: &Session,
+        f: impl FnOnce(&Session) -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let id = self.begin(session);
+        let result = f(session);
+        let cancelled = self.end(id);
+
+        match result {
+            Err(e) if cancelled => Err(Box::new(QueryCancelled {
+                message: e.to_string(),
+            })),
+            result => result,
+        }
+    }
+
+    /// Registers a query starting on `session` so tokens can cancel it; returns
+    /// its id, or `None` if the session cannot be cancelled.
+    pub(crate) fn begin(&self, session: &Session) -> Option<u64> {
+        self.register(Arc::clone(session.cancel_handle()?))
+    }
+
+    // Records a running query cancelled through `handle`
+    fn register(&self, handle: Arc<CancelHandle>) -> Option<u64> {
+        let mut active = self.active.lock().ok()?;
+        let id = active.next_id;
+        active.next_id += 1;
+        active.queries.push(ActiveQuery {
+            id,
+            handle,
+            cancelled: false,
+        });
+        Some(id)
+    }
+
+    /// Unregisters a query started with [`CancelState::begin`] and returns whether
+    /// a token cancelled it.
+    pub(crate) fn end(&self, id: Option<u64>) -> bool {
+        let Some(id) = id else {
+            return false;
+        };
+        match self.active.lock() {
+            Ok(mut active) => {
+                let index = active.queries.iter().position(|q| q.id == id);
+                index.is_some_and(|index| active.queries.remove(index).cancelled)
+            }
+            Err(_) => false,
+        }
+    }
+
+    // Sends a cancel request for every running query, even if an earlier request
+    // fails, and reports the first failure
+    fn cancel(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut active = self
+            .active
+            .lock()
+            .map_err(|_| "Cancel state lock poisoned")?;
+        if active.queries.is_empty() {
+            return Ok(false);
+        }
+        let mut first_error = None;
+        for query in active.queries.iter_mut() {
+            debug!("Sending cancel request for query {}", query.id);
+            match query.handle.cancel() {
+                Ok(()) => query.cancelled = true,
+                Err(e) => {
+                    debug!("Cancel request for query {} failed: {}", query.id, e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e.into()),
+            None => Ok(true),
+        }
+    }
+}
+
+/// A handle that cancels queries running on a client from another thread.
+///
+/// Obtain a token from [`PgwireLite::cancel_token`](crate::PgwireLite::cancel_token)
+/// or [`PooledConnection::cancel_token`](crate::PooledConnection::cancel_token),
+/// before or while a query runs, and call [`CancelToken::cancel`] from any thread.
+/// The interrupted query returns a [`QueryCancelled`] error once the connection
+/// has been drained, and the connection stays usable.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::thread;
+/// use std::time::Duration;
+/// use pgwire_lite::{PgwireLite, QueryCancelled};
+///
+/// let client = PgwireLite::new("localhost", 5444, false, "default")
+///     .expect("Failed to create client");
+/// let token = client.cancel_token();
+///
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_secs(30));
+///     token.cancel().expect("Failed to send cancel request");
+/// });
+///
+/// match client.query("SELECT * FROM aws.ec2.instances") {
+///     Err(e) if e.is::<QueryCancelled>() => println!("Query cancelled"),
+///     other => println!("{:?}", other.map(|r| r.row_count)),
+/// }
+/// ```
+#[derive(Clone)]
+pub struct CancelToken {
+    state: Arc<CancelState>,
+}
+
+impl CancelToken {
+    pub(crate) fn new(state: Arc<CancelState>) -> Self {
+        CancelToken { state }
+    }
+
+    /// Requests cancellation of the queries currently running on the client.
+    ///
+    /// Returns `Ok(false)` if no query was running, in which case nothing is
+    /// cancelled; a query started afterwards is not affected. Cancellation is
+    /// best effort: a query that completes before the server acts on the
+    /// request returns its result normally. If a request cannot be sent, the
+    /// remaining queries are still cancelled and the first error is returned.
+    pub fn cancel(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        self.state.cancel()
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken").finish_non_exhaustive()
+    }
+}
+
+/// Error returned by a query that was interrupted through a [`CancelToken`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryCancelled {
+    /// The error message reported by the server.
+    pub message: String,
+}
+
+impl fmt::Display for QueryCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "query cancelled: {}", self.message)
+    }
+}
+
+impl std::error::Error for QueryCancelled {}
+
+/// Error returned by a query that exceeded its time limit.
+///
+/// The query is cancelled when the limit is reached. If the server did not
+/// acknowledge the cancel in time, the connection is discarded rather than reused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryTimeout {
+    /// How long the query ran before it was cancelled.
+    pub elapsed: Duration,
+    /// Whether the server stopped the query after the cancel request.
+    pub cancel_acknowledged: bool,
+}
+
+impl fmt::Display for QueryTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "query timed out after {:?} ({})",
+            self.elapsed,
+            if self.cancel_acknowledged {
+                "cancel acknowledged"
+            } else {
+                "cancel not acknowledged, connection discarded"
+            }
+        )
+    }
+}
+
+impl std::error::Error for QueryTimeout {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    // A handle without a libpq cancel object; PQcancel rejects it and
+    // PQfreeCancel ignores it
+    fn null_handle() -> Arc<CancelHandle> {
+        Arc::new(CancelHandle {
+            cancel: ptr::null_mut(),
+        })
+    }
+
+    fn running(state: &CancelState) -> usize {
+        state.active.lock().unwrap().queries.len()
+    }
+
+    #[test]
+    fn queries_get_increasing_ids() {
+        let state = CancelState::default();
+        assert_eq!(state.register(null_handle()), Some(0));
+        assert_eq!(state.register(null_handle()), Some(1));
+        assert_eq!(running(&state), 2);
+    }
+
+    #[test]
+    fn end_unregisters_the_query_once() {
+        let state = CancelState::default();
+        let first = state.register(null_handle());
+        let second = state.register(null_handle());
+        assert!(!state.end(first));
+        assert_eq!(running(&state), 1);
+        assert!(!state.end(first));
+        assert!(!state.end(second));
+        assert_eq!(running(&state), 0);
+    }
+
+    #[test]
+    fn end_without_an_id_is_not_cancelled() {
+        let state = CancelState::default();
+        state.register(null_handle());
+        assert!(!state.end(None));
+        assert_eq!(running(&state), 1);
+    }
+
+    #[test]
+    fn end_reports_whether_the_query_was_cancelled() {
+        let state = CancelState::default();
+        let id = state.register(null_handle());
+        state.active.lock().unwrap().queries[0].cancelled = true;
+        assert!(state.end(id));
+    }
+
+    #[test]
+    fn cancelling_with_nothing_running_does_nothing() {
+        let token = CancelToken::new(Arc::new(CancelState::default()));
+        assert!(!token.cancel().unwrap());
+    }
+
+    #[test]
+    fn failed_cancel_requests_leave_queries_uncancelled() {
+        let state = Arc::new(CancelState::default());
+        let first = state.register(null_handle());
+        let second = state.register(null_handle());
+        let token = CancelToken::new(Arc::clone(&state));
+        assert!(token.cancel().is_err());
+        // Neither query is marked cancelled, so their errors are not rewritten
+        assert!(!state.end(first));
+        assert!(!state.end(second));
+    }
+
+    #[test]
+    fn cancelled_error_keeps_the_server_message() {
+        let error = QueryCancelled {
+            message: "canceling statement due to user request".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "query cancelled: canceling statement due to user request"
+        );
+    }
+
+    #[test]
+    fn timeout_error_says_whether_the_connection_was_kept() {
+        let acknowledged = QueryTimeout {
+            elapsed: Duration::from_millis(1_500),
+            cancel_acknowledged: true,
+        };
+        assert_eq!(
+            acknowledged.to_string(),
+            "query timed out after 1.5s (cancel acknowledged)"
+        );
+
+        let unacknowledged = QueryTimeout {
+            elapsed: Duration::from_secs(2),
+            cancel_acknowledged: false,
+        };
+        assert_eq!(
+            unacknowledged.to_string(),
+            "query timed out after 2s (cancel not acknowledged, connection discarded)"
+        );
+    }
+}
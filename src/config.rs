@@ -0,0 +1,1312 @@
+// src/config.rs
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::credentials::{CredentialCache, CredentialProvider, Credentials};
+use crate::dsn::DEFAULT_PORT;
+use crate::notices::Verbosity;
+use crate::retry::RetryPolicy;
+use crate::tls::{SslMode, TlsConfig};
+
+// Statements kept prepared per connection unless configured otherwise
+const DEFAULT_STATEMENT_CACHE_SIZE: usize = 100;
+
+/// Connection settings used by [`PgwireLite`](crate::PgwireLite) to open connections.
+///
+/// `ConnectionConfig` is a builder: start from [`ConnectionConfig::new`] with the
+/// server address and chain setters for the optional parameters. Every setting maps
+/// onto a libpq connection keyword, and anything without a dedicated setter can be
+/// passed through with [`ConnectionConfig::param`].
+///
+/// # Example
+///
+/// ```
+/// use pgwire_lite::{ConnectionConfig, Verbosity};
+///
+/// let config = ConnectionConfig::new("localhost", 5432)
+///     .user("postgres")
+///     .dbname("inventory")
+///     .application_name("inventory-sync")
+///     .connect_timeout(5)
+///     .verbosity(Verbosity::Verbose);
+///
+/// assert_eq!(config.get_user(), Some("postgres"));
+/// assert!(config.to_conninfo().contains("dbname='inventory'"));
+/// ```
+#[derive(Clone)]
+pub struct ConnectionConfig {
+    hosts: Vec<Host>,
+    user: Option<String>,
+    dbname: Option<String>,
+    password: Option<String>,
+    passfile: Option<PathBuf>,
+    service: Option<String>,
+    channel_binding: Option<ChannelBinding>,
+    requirepeer: Option<String>,
+    credentials: Option<CredentialCache>,
+    application_name: Option<String>,
+    connect_timeout: Option<u32>,
+    options: Option<String>,
+    settings: Vec<(String, String)>,
+    init_statements: Vec<String>,
+    keepalives: Option<Keepalives>,
+    client_encoding: Option<String>,
+    target_session_attrs: Option<TargetSessionAttrs>,
+    load_balance_hosts: Option<LoadBalanceHosts>,
+    tls: TlsConfig,
+    verbosity: Verbosity,
+    persistent: bool,
+    query_timeout: Option<Duration>,
+    statement_cache_size: usize,
+    retry_policy: Option<RetryPolicy>,
+    params: Vec<(String, String)>,
+}
+
+/// A server address in the connection's host list.
+///
+/// Following libpq, a `host` that starts with `/` is the directory containing a
+/// Unix-domain socket and one that starts with `@` names a socket in the Linux
+/// abstract namespace; the socket file is `.s.PGSQL.<port>` in either case.
+/// Any other value is a host name or IP address reached over TCP.
+///
+/// # Example
+///
+/// ```
+/// use pgwire_lite::Host;
+///
+/// let socket = Host::unix_socket("/var/run/postgresql", 5432);
+/// assert!(socket.is_unix_socket());
+/// assert_eq!(socket.to_string(), "/var/run/postgresql/.s.PGSQL.5432");
+///
+/// let tcp = Host::tcp("db.example.com", 5444);
+/// assert!(!tcp.is_unix_socket());
+/// assert_eq!(tcp.to_string(), "db.example.com:5444");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Host {
+    /// Host name or IP address of the server, or a Unix-domain socket directory
+    /// (`/...`) or abstract socket name (`@...`).
+    pub host: String,
+
+    /// Port the server is listening on, which also names the socket file for
+    /// Unix-domain sockets.
+    pub port: u16,
+}
+
+impl Host {
+    /// Creates a TCP address from a host name or IP address.
+    pub fn tcp(host: &str, port: u16) -> Self {
+        Host {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    /// Creates the address of a Unix-domain socket in `directory`, which must be
+    /// an absolute path.
+    pub fn unix_socket(directory: impl AsRef<Path>, port: u16) -> Self {
+        Host {
+            host: directory.as_ref().to_string_lossy().into_owned(),
+            port,
+        }
+    }
+
+    /// Creates the address of a socket in the Linux abstract namespace. `name` is
+    /// given without the leading `@`.
+    pub fn abstract_socket(name: &str, port: u16) -> Self {
+        Host {
+            host: format!("@{}", name),
+            port,
+        }
+    }
+
+    /// Returns whether this is a Unix-domain socket, including abstract sockets.
+    pub fn is_unix_socket(&self) -> bool {
+        self.host.starts_with('/') || self.is_abstract_socket()
+    }
+
+    /// Returns whether this is a socket in the Linux abstract namespace.
+    pub fn is_abstract_socket(&self) -> bool {
+        self.host.starts_with('@')
+    }
+
+    /// Returns the full path of the socket file, e.g.
+    /// `/var/run/postgresql/.s.PGSQL.5432`, or `None` for a TCP address.
+    pub fn socket_path(&self) -> Option<String> {
+        if !self.is_unix_socket() {
+            return None;
+        }
+        Some(format!(
+            "{}/.s.PGSQL.{}",
+            self.host.trim_end_matches('/'),
+            self.port
+        ))
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.socket_path() {
+            Some(path) => write!(f, "{}", path),
+            None if self.host.contains(':') => write!(f, "[{}]:{}", self.host, self.port),
+            None => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// TCP keepalive settings for a connection.
+///
+/// Fields left as `None` fall back to the operating system defaults.
+#[derive(Debug, Clone, Default)]
+pub struct Keepalives {
+    /// Seconds of inactivity after which a keepalive is sent.
+    pub idle: Option<u32>,
+
+    /// Seconds between unacknowledged keepalives.
+    pub interval: Option<u32>,
+
+    /// Number of lost keepalives before the connection is considered dead.
+    pub count: Option<u32>,
+}
+
+/// Which kind of server a connection must reach to be accepted.
+///
+/// Mirrors the libpq `target_session_attrs` connection parameter. Servers in the
+/// host list that do not qualify are skipped in favour of the next host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetSessionAttrs {
+    /// Any server is acceptable.
+    Any,
+    /// The server must accept read-write transactions by default.
+    ReadWrite,
+    /// The server must not accept read-write transactions by default.
+    ReadOnly,
+    /// The server must not be in hot standby mode.
+    Primary,
+    /// The server must be in hot standby mode.
+    Standby,
+    /// Prefer a server in hot standby mode, falling back to any server.
+    PreferStandby,
+}
+
+impl TargetSessionAttrs {
+    /// Returns the libpq `target_session_attrs` value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TargetSessionAttrs::Any => "any",
+            TargetSessionAttrs::ReadWrite => "read-write",
+            TargetSessionAttrs::ReadOnly => "read-only",
+            TargetSessionAttrs::Primary => "primary",
+            TargetSessionAttrs::Standby => "standby",
+            TargetSessionAttrs::PreferStandby => "prefer-standby",
+        }
+    }
+}
+
+impl fmt::Display for TargetSessionAttrs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for TargetSessionAttrs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(TargetSessionAttrs::Any),
+            "read-write" => Ok(TargetSessionAttrs::ReadWrite),
+            "read-only" => Ok(TargetSessionAttrs::ReadOnly),
+            "primary" => Ok(TargetSessionAttrs::Primary),
+            "standby" => Ok(TargetSessionAttrs::Standby),
+            "prefer-standby" => Ok(TargetSessionAttrs::PreferStandby),
+            _ => Err(format!("invalid target_session_attrs value: \"{}\"", s)),
+        }
+    }
+}
+
+/// The order in which hosts in the host list are tried.
+///
+/// Mirrors the libpq `load_balance_hosts` connection parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadBalanceHosts {
+    /// Try the hosts in the order they are listed.
+    Disable,
+    /// Try the hosts in a random order, spreading connections across them.
+    Random,
+}
+
+impl LoadBalanceHosts {
+    /// Returns the libpq `load_balance_hosts` value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoadBalanceHosts::Disable => "disable",
+            LoadBalanceHosts::Random => "random",
+        }
+    }
+}
+
+impl fmt::Display for LoadBalanceHosts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for LoadBalanceHosts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(LoadBalanceHosts::Disable),
+            "random" => Ok(LoadBalanceHosts::Random),
+            _ => Err(format!("invalid load_balance_hosts value: \"{}\"", s)),
+        }
+    }
+}
+
+/// Whether SCRAM channel binding is used during authentication.
+///
+/// Mirrors the libpq `channel_binding` connection parameter. Channel binding ties
+/// SCRAM authentication to the TLS session, so it is only available over TLS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelBinding {
+    /// Never use channel binding.
+    Disable,
+    /// Use channel binding if the server supports it.
+    Prefer,
+    /// Fail the connection unless channel binding is used.
+    Require,
+}
+
+impl ChannelBinding {
+    /// Returns the libpq `channel_binding` value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelBinding::Disable => "disable",
+            ChannelBinding::Prefer => "prefer",
+            ChannelBinding::Require => "require",
+        }
+    }
+}
+
+impl fmt::Display for ChannelBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ChannelBinding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(ChannelBinding::Disable),
+            "prefer" => Ok(ChannelBinding::Prefer),
+            "require" => Ok(ChannelBinding::Require),
+            _ => Err(format!("invalid channel_binding value: \"{}\"", s)),
+        }
+    }
+}
+
+impl ConnectionConfig {
+    /// Creates a configuration for the given server with default settings.
+    ///
+    /// The defaults match the historical behaviour of [`PgwireLite::new`](crate::PgwireLite::new):
+    /// no TLS, `application_name=pgwire-lite-client`, `connect_timeout=10` and
+    /// `client_encoding=UTF8`.
+    pub fn new(host: &str, port: u16) -> Self {
+        ConnectionConfig {
+            hosts: vec![Host {
+                host: host.to_string(),
+                port,
+            }],
+            application_name: Some(String::from("pgwire-lite-client")),
+            connect_timeout: Some(10),
+            client_encoding: Some(String::from("UTF8")),
+            tls: TlsConfig::default(),
+            ..Self::unset()
+        }
+    }
+
+    // A configuration that sets no connection parameters at all, so libpq's
+    // defaults and the PG* environment variables apply
+    pub(crate) fn unset() -> Self {
+        ConnectionConfig {
+            hosts: Vec::new(),
+            user: None,
+            dbname: None,
+            password: None,
+            passfile: None,
+            service: None,
+            channel_binding: None,
+            requirepeer: None,
+            credentials: None,
+            application_name: None,
+            connect_timeout: None,
+            options: None,
+            settings: Vec::new(),
+            init_statements: Vec::new(),
+            keepalives: None,
+            client_encoding: None,
+            target_session_attrs: None,
+            load_balance_hosts: None,
+            tls: TlsConfig::unset(),
+            verbosity: Verbosity::Default,
+            persistent: false,
+            query_timeout: None,
+            statement_cache_size: DEFAULT_STATEMENT_CACHE_SIZE,
+            retry_policy: None,
+            params: Vec::new(),
+        }
+    }
+
+    /// Creates a configuration that takes its connection settings from a service
+    /// entry in `pg_service.conf`.
+    ///
+    /// No host is set, so the host and port come from the service entry. libpq looks
+    /// the service up in `PGSERVICEFILE` (default `~/.pg_service.conf`) and then in
+    /// `pg_service.conf` under `PGSYSCONFDIR`. Settings made on the returned
+    /// configuration take precedence over the service entry; note that
+    /// `application_name`, `connect_timeout` and `client_encoding` always have a value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use pgwire_lite::ConnectionConfig;
+    ///
+    /// let config = ConnectionConfig::from_service("stackql").user("reporting");
+    /// assert_eq!(config.get_service(), Some("stackql"));
+    /// assert!(config.get_hosts().is_empty());
+    /// assert!(!config.to_conninfo().contains("host="));
+    /// ```
+    pub fn from_service(service: &str) -> Self {
+        Self::new("", DEFAULT_PORT).service(service).clear_hosts()
+    }
+
+    /// Creates a configuration for a server reached through the Unix-domain socket
+    /// `.s.PGSQL.<port>` in `directory`, with default settings.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use pgwire_lite::ConnectionConfig;
+    ///
+    /// let config = ConnectionConfig::from_unix_socket("/var/run/postgresql", 5432)
+    ///     .requirepeer("postgres");
+    /// assert!(config.get_hosts()[0].is_unix_socket());
+    /// assert!(config.to_conninfo().contains("host='/var/run/postgresql'"));
+    /// ```
+    pub fn from_unix_socket(directory: impl AsRef<Path>, port: u16) -> Self {
+        Self::new("", port).hosts(vec![Host::unix_socket(directory, port)])
+    }
+
+    /// Sets the host name or IP address of the first server in the host list.
+    ///
+    /// A value starting with `/` or `@` is a Unix-domain socket directory or
+    /// abstract socket name; see [`Host`].
+    pub fn host(mut self, host: &str) -> Self {
+        match self.hosts.first_mut() {
+            Some(first) => first.host = host.to_string(),
+            None => return self.add_host(host, DEFAULT_PORT),
+        }
+        self
+    }
+
+    /// Sets the port of the first server in the host list.
+    pub fn port(mut self, port: u16) -> Self {
+        match self.hosts.first_mut() {
+            Some(first) => first.port = port,
+            None => return self.add_host("localhost", port),
+        }
+        self
+    }
+
+    /// Appends another server to the host list.
+    ///
+    /// libpq tries the hosts in order until a connection succeeds (and, if
+    /// [`ConnectionConfig::target_session_attrs`] is set, reaches a suitable server).
+    pub fn add_host(mut self, host: &str, port: u16) -> Self {
+        self.hosts.push(Host {
+            host: host.to_string(),
+            port,
+        });
+        self
+    }
+
+    /// Appends a server reached through the Unix-domain socket `.s.PGSQL.<port>` in
+    /// `directory` to the host list.
+    pub fn add_unix_socket(mut self, directory: impl AsRef<Path>, port: u16) -> Self {
+        self.hosts.push(Host::unix_socket(directory, port));
+        self
+    }
+
+    /// Appends a server reached through a socket in the Linux abstract namespace
+    /// to the host list. `name` is given without the leading `@`.
+    pub fn add_abstract_socket(mut self, name: &str, port: u16) -> Self {
+        self.hosts.push(Host::abstract_socket(name, port));
+        self
+    }
+
+    /// Replaces the whole host list.
+    ///
+    /// An empty list is ignored; use [`ConnectionConfig::from_service`] to take the
+    /// host list from a service entry instead.
+    pub fn hosts(mut self, hosts: Vec<Host>) -> Self {
+        if !hosts.is_empty() {
+            self.hosts = hosts;
+        }
+        self
+    }
+
+    /// Sets the user name to connect as.
+    pub fn user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    /// Sets the database name.
+    pub fn dbname(mut self, dbname: &str) -> Self {
+        self.dbname = Some(dbname.to_string());
+        self
+    }
+
+    /// Sets the password used if the server demands password authentication.
+    ///
+    /// libpq negotiates the authentication method with the server, including
+    /// SCRAM-SHA-256 and MD5. Without a password, libpq falls back to the
+    /// `PGPASSWORD` environment variable and then the password file.
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// Sets the password file to look up passwords in.
+    ///
+    /// Entries use the `.pgpass` format `hostname:port:database:username:password`.
+    /// Defaults to `~/.pgpass` (`%APPDATA%\postgresql\pgpass.conf` on Windows); libpq
+    /// ignores the file if it is readable by group or others.
+    pub fn passfile(mut self, path: impl AsRef<Path>) -> Self {
+        self.passfile = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the `pg_service.conf` service entry to read default settings from.
+    ///
+    /// Settings made on this configuration, including the host list, take precedence
+    /// over the service entry. Use [`ConnectionConfig::from_service`] to take the host
+    /// and port from the service entry as well.
+    pub fn service(mut self, service: &str) -> Self {
+        self.service = Some(service.to_string());
+        self
+    }
+
+    /// Sets a provider that supplies the password (and optionally the user) for
+    /// each new connection.
+    ///
+    /// The provider is called when a connection is opened, whether by
+    /// [`PgwireLite`](crate::PgwireLite) or by a [`Pool`](crate::Pool), and its
+    /// credentials are cached until they expire. They take precedence over
+    /// [`ConnectionConfig::password`]. See [`CredentialProvider`].
+    pub fn credential_provider(mut self, provider: impl CredentialProvider + 'static) -> Self {
+        self.credentials = Some(CredentialCache::new(provider));
+        self
+    }
+
+    /// Sets whether SCRAM channel binding is used.
+    pub fn channel_binding(mut self, channel_binding: ChannelBinding) -> Self {
+        self.channel_binding = Some(channel_binding);
+        self
+    }
+
+    /// Requires the server process behind a Unix-domain socket to run as the given
+    /// operating system user, checked with peer credentials before authenticating.
+    ///
+    /// Only applies to Unix-domain socket connections; validation fails if every
+    /// host in the host list is a TCP address.
+    pub fn requirepeer(mut self, user: &str) -> Self {
+        self.requirepeer = Some(user.to_string());
+        self
+    }
+
+    /// Sets the `application_name` reported to the server.
+    pub fn application_name(mut self, application_name: &str) -> Self {
+        self.application_name = Some(application_name.to_string());
+        self
+    }
+
+    /// Sets the maximum time to wait while connecting, in seconds.
+    ///
+    /// A value of zero waits indefinitely.
+    pub fn connect_timeout(mut self, seconds: u32) -> Self {
+        self.connect_timeout = Some(seconds);
+        self
+    }
+
+    /// Sets the command-line options sent to the server at connection start,
+    /// for example `-c search_path=public`.
+    pub fn options(mut self, options: &str) -> Self {
+        self.options = Some(options.to_string());
+        self
+    }
+
+    /// Sets a server configuration parameter (GUC) for every connection, such as
+    /// `search_path` or `statement_timeout`.
+    ///
+    /// Settings are sent at connection start as `-c name=value` in the `options`
+    /// parameter, after any options set with [`ConnectionConfig::options`]. An
+    /// invalid setting makes the connection fail.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use pgwire_lite::ConnectionConfig;
+    ///
+    /// let config = ConnectionConfig::new("localhost", 5432)
+    ///     .setting("search_path", "inventory, public")
+    ///     .setting("statement_timeout", "30s");
+    ///
+    /// assert!(config
+    ///     .to_conninfo()
+    ///     .contains(r"options='-c search_path=inventory,\\ public -c statement_timeout=30s'"));
+    /// ```
+    pub fn setting(mut self, name: &str, value: &str) -> Self {
+        self.settings.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Adds a statement that is executed on every new connection before it is used.
+    ///
+    /// Use this for session setup that cannot be expressed as a
+    /// [`ConnectionConfig::setting`], such as StackQL session commands. Statements
+    /// run in the order they were added; if one fails, opening the connection fails
+    /// with a [`DbError`](crate::DbError) of kind
+    /// [`Connect`](crate::DbErrorKind::Connect) that names the statement and keeps
+    /// the SQLSTATE the server reported.
+    pub fn init_statement(mut self, statement: &str) -> Self {
+        self.init_statements.push(statement.to_string());
+        self
+    }
+
+    /// Enables TCP keepalives with the given settings.
+    pub fn keepalives(mut self, keepalives: Keepalives) -> Self {
+        self.keepalives = Some(keepalives);
+        self
+    }
+
+    /// Sets the client encoding for the connection.
+    pub fn client_encoding(mut self, client_encoding: &str) -> Self {
+        self.client_encoding = Some(client_encoding.to_string());
+        self
+    }
+
+    /// Sets whether to use TLS encryption for the connection.
+    ///
+    /// This is shorthand for switching the TLS mode between
+    /// [`SslMode::VerifyFull`] and [`SslMode::Disable`]; certificate paths
+    /// configured with [`ConnectionConfig::tls`] are kept.
+    pub fn use_tls(mut self, use_tls: bool) -> Self {
+        self.tls = self.tls.mode(if use_tls {
+            SslMode::VerifyFull
+        } else {
+            SslMode::Disable
+        });
+        self
+    }
+
+    /// Sets the TLS mode, certificates and protocol versions for the connection.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Sets which kind of server the connection must reach.
+    ///
+    /// Hosts that are reachable but do not qualify, for example a read-only
+    /// replica when [`TargetSessionAttrs::ReadWrite`] is requested, are skipped.
+    pub fn target_session_attrs(mut self, attrs: TargetSessionAttrs) -> Self {
+        self.target_session_attrs = Some(attrs);
+        self
+    }
+
+    /// Sets the order in which hosts in the host list are tried.
+    ///
+    /// `load_balance_hosts` requires libpq 16; with older versions of libpq the
+    /// host list is shuffled before it is passed to libpq instead.
+    pub fn load_balance_hosts(mut self, load_balance_hosts: LoadBalanceHosts) -> Self {
+        self.load_balance_hosts = Some(load_balance_hosts);
+        self
+    }
+
+    /// Sets the error/notice verbosity level.
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Sets whether the client keeps one long-lived connection open.
+    ///
+    /// By default [`PgwireLite`](crate::PgwireLite) opens a new connection for every
+    /// query. In persistent mode it opens a connection on first use and reuses it,
+    /// so session state such as `SET` values, temporary tables and StackQL
+    /// authentication context survives between queries. A connection found to be
+    /// broken is replaced automatically; call [`PgwireLite::close`](crate::PgwireLite::close)
+    /// to release it explicitly.
+    pub fn persistent(mut self, persistent: bool) -> Self {
+        self.persistent = persistent;
+        self
+    }
+
+    /// Sets the default time limit for executing a query.
+    ///
+    /// A query still running when the limit is reached is cancelled and fails with
+    /// [`QueryTimeout`](crate::QueryTimeout). The limit covers execution only, not
+    /// opening the connection, which is bounded by [`ConnectionConfig::connect_timeout`].
+    /// Individual queries can use a different limit with
+    /// [`PgwireLite::query_with_timeout`](crate::PgwireLite::query_with_timeout).
+    pub fn query_timeout(mut self, timeout: Duration) -> Self {
+        self.query_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how many statements each long-lived connection keeps prepared for
+    /// [`PgwireLite::query_params`](crate::PgwireLite::query_params).
+    ///
+    /// On persistent and pooled connections, a query with parameters is prepared
+    /// on first use and the server-side statement is reused for later queries with
+    /// the same SQL text and parameter types. When the cache is full, the least
+    /// recently used statement is deallocated. The default is 100; `0` disables
+    /// the cache, so every query is parsed and planned again.
+    pub fn statement_cache_size(mut self, size: usize) -> Self {
+        self.statement_cache_size = size;
+        self
+    }
+
+    /// Sets how queries that fail with a transient error are retried.
+    ///
+    /// Without a policy, queries are attempted once. See [`RetryPolicy`].
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Adds an arbitrary libpq connection keyword.
+    ///
+    /// Parameters added here are appended after the built-in settings, so they
+    /// override any value produced by the dedicated setters.
+    pub fn param(mut self, keyword: &str, value: &str) -> Self {
+        self.params.push((keyword.to_string(), value.to_string()));
+        self
+    }
+
+    /// Returns the first configured host, or an empty string if the host comes from a service entry.
+    pub fn get_host(&self) -> &str {
+        self.hosts.first().map_or("", |h| h.host.as_str())
+    }
+
+    /// Returns the port of the first configured host, or the default port 5432 if
+    /// the host comes from a service entry.
+    pub fn get_port(&self) -> u16 {
+        self.hosts.first().map_or(DEFAULT_PORT, |h| h.port)
+    }
+
+    /// Returns every configured host, in connection order.
+    pub fn get_hosts(&self) -> &[Host] {
+        &self.hosts
+    }
+
+    /// Returns the configured password, if any.
+    pub fn get_password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+
+    /// Returns the configured user, if any.
+    pub fn get_user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Returns the configured database name, if any.
+    pub fn get_dbname(&self) -> Option<&str> {
+        self.dbname.as_deref()
+    }
+
+    /// Returns the server configuration parameters applied to every connection.
+    pub fn get_settings(&self) -> &[(String, String)] {
+        &self.settings
+    }
+
+    /// Returns the statements executed on every new connection.
+    pub fn get_init_statements(&self) -> &[String] {
+        &self.init_statements
+    }
+
+    /// Returns the password file, if set.
+    pub fn get_passfile(&self) -> Option<&Path> {
+        self.passfile.as_deref()
+    }
+
+    /// Returns the service name, if set.
+    pub fn get_service(&self) -> Option<&str> {
+        self.service.as_deref()
+    }
+
+    /// Returns whether a credential provider is configured.
+    pub fn has_credential_provider(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    /// Returns the channel binding setting, if set.
+    pub fn get_channel_binding(&self) -> Option<ChannelBinding> {
+        self.channel_binding
+    }
+
+    /// Returns the operating system user the server must run as, if set.
+    pub fn get_requirepeer(&self) -> Option<&str> {
+        self.requirepeer.as_deref()
+    }
+
+    /// Returns the connection timeout in seconds, if set.
+    pub fn get_connect_timeout(&self) -> Option<u32> {
+        self.connect_timeout
+    }
+
+    /// Returns the configured `application_name`, if set.
+    pub fn get_application_name(&self) -> Option<&str> {
+        self.application_name.as_deref()
+    }
+
+    /// Returns the configured client encoding, if set.
+    pub fn get_client_encoding(&self) -> Option<&str> {
+        self.client_encoding.as_deref()
+    }
+
+    /// Returns whether TLS is enabled, i.e. the TLS mode is not [`SslMode::Disable`].
+    pub fn get_use_tls(&self) -> bool {
+        self.tls.get_mode() != SslMode::Disable
+    }
+
+    /// Returns the TLS settings.
+    pub fn get_tls(&self) -> &TlsConfig {
+        &self.tls
+    }
+
+    /// Returns the required kind of server, if set.
+    pub fn get_target_session_attrs(&self) -> Option<TargetSessionAttrs> {
+        self.target_session_attrs
+    }
+
+    /// Returns the host ordering, if set.
+    pub fn get_load_balance_hosts(&self) -> Option<LoadBalanceHosts> {
+        self.load_balance_hosts
+    }
+
+    /// Returns the configured verbosity level.
+    pub fn get_verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    /// Returns the default query time limit, if set.
+    pub fn get_query_timeout(&self) -> Option<Duration> {
+        self.query_timeout
+    }
+
+    /// Returns the number of statements each long-lived connection keeps prepared.
+    pub fn get_statement_cache_size(&self) -> usize {
+        self.statement_cache_size
+    }
+
+    /// Returns the retry policy, if set.
+    pub fn get_retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry_policy.as_ref()
+    }
+
+    /// Returns whether persistent connection mode is enabled.
+    pub fn get_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// Returns the additional libpq keywords set with [`ConnectionConfig::param`].
+    pub fn get_params(&self) -> &[(String, String)] {
+        &self.params
+    }
+
+    // Collects every keyword/value pair in the order they are sent to libpq
+    fn conninfo_pairs(&self) -> Vec<(&str, String)> {
+        let mut pairs = Vec::new();
+        if let Some(service) = &self.service {
+            pairs.push(("service", service.clone()));
+        }
+        if !self.hosts.is_empty() {
+            let hosts = self.hosts.iter().map(|h| h.host.as_str());
+            let ports = self.hosts.iter().map(|h| h.port.to_string());
+            pairs.push(("host", hosts.collect::<Vec<_>>().join(",")));
+            pairs.push(("port", ports.collect::<Vec<_>>().join(",")));
+        }
+        if let Some(user) = &self.user {
+            pairs.push(("user", user.clone()));
+        }
+        if let Some(dbname) = &self.dbname {
+            pairs.push(("dbname", dbname.clone()));
+        }
+        if let Some(password) = &self.password {
+            pairs.push(("password", password.clone()));
+        }
+        if let Some(passfile) = &self.passfile {
+            pairs.push(("passfile", passfile.to_string_lossy().into_owned()));
+        }
+        if let Some(channel_binding) = self.channel_binding {
+            pairs.push(("channel_binding", channel_binding.as_str().to_string()));
+        }
+        if let Some(requirepeer) = &self.requirepeer {
+            pairs.push(("requirepeer", requirepeer.clone()));
+        }
+        pairs.extend(self.tls.conninfo_pairs());
+        if let Some(application_name) = &self.application_name {
+            pairs.push(("application_name", application_name.clone()));
+        }
+        if let Some(timeout) = self.connect_timeout {
+            pairs.push(("connect_timeout", timeout.to_string()));
+        }
+        if let Some(client_encoding) = &self.client_encoding {
+            pairs.push(("client_encoding", client_encoding.clone()));
+        }
+        let mut options: Vec<String> = self.options.iter().cloned().collect();
+        for (name, value) in &self.settings {
+            options.push(format!(
+                "-c {}={}",
+                escape_option_value(name),
+                escape_option_value(value)
+            ));
+        }
+        if !options.is_empty() {
+            pairs.push(("options", options.join(" ")));
+        }
+        if let Some(keepalives) = &self.keepalives {
+            pairs.push(("keepalives", String::from("1")));
+            if let Some(idle) = keepalives.idle {
+                pairs.push(("keepalives_idle", idle.to_string()));
+            }
+            if let Some(interval) = keepalives.interval {
+                pairs.push(("keepalives_interval", interval.to_string()));
+            }
+            if let Some(count) = keepalives.count {
+                pairs.push(("keepalives_count", count.to_string()));
+            }
+        }
+        if let Some(attrs) = self.target_session_attrs {
+            pairs.push(("target_session_attrs", attrs.as_str().to_string()));
+        }
+        if let Some(load_balance_hosts) = self.load_balance_hosts {
+            pairs.push((
+                "load_balance_hosts",
+                load_balance_hosts.as_str().to_string(),
+            ));
+        }
+        for (keyword, value) in &self.params {
+            pairs.push((keyword.as_str(), value.clone()));
+        }
+        pairs
+    }
+
+    // libpq before 16 rejects load_balance_hosts, so random ordering is emulated
+    // by shuffling the host list
+    pub(crate) fn with_shuffled_hosts(&self) -> ConnectionConfig {
+        let mut config = self.clone();
+        config.load_balance_hosts = None;
+        shuffle(&mut config.hosts);
+        config
+    }
+
+    /// Checks that the settings are consistent before any connection is attempted.
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.tls.validate()?;
+        if self.channel_binding == Some(ChannelBinding::Require)
+            && self.tls.get_mode() == SslMode::Disable
+        {
+            return Err("channel_binding=require needs TLS, but sslmode is disable".into());
+        }
+        if self.requirepeer.is_some()
+            && !self.hosts.is_empty()
+            && !self.hosts.iter().any(Host::is_unix_socket)
+        {
+            return Err(
+                "requirepeer needs a Unix-domain socket host, but every host is TCP".into(),
+            );
+        }
+        Ok(())
+    }
+
+    // The credential provider and its cache, if one is configured
+    pub(crate) fn credential_cache(&self) -> Option<&CredentialCache> {
+        self.credentials.as_ref()
+    }
+
+    // Applies credentials from a provider on top of the configured user and password
+    pub(crate) fn with_credentials(&self, credentials: &Credentials) -> ConnectionConfig {
+        let mut config = self.clone();
+        if let Some(user) = &credentials.user {
+            config.user = Some(user.clone());
+        }
+        config.password = Some(credentials.password.clone());
+        config
+    }
+
+    // The host list for log and error messages
+    pub(crate) fn describe_hosts(&self) -> String {
+        if self.hosts.is_empty() {
+            return match &self.service {
+                Some(service) => format!("service {}", service),
+                None => String::from("default host"),
+            };
+        }
+        let hosts: Vec<String> = self.hosts.iter().map(Host::to_string).collect();
+        hosts.join(",")
+    }
+
+    // Removes the host list so the host and port come from a service entry
+    pub(crate) fn clear_hosts(mut self) -> Self {
+        self.hosts.clear();
+        self
+    }
+
+    /// Builds the libpq connection string for this configuration.
+    ///
+    /// Values are single-quoted and escaped, so they may contain spaces,
+    /// quotes and backslashes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use pgwire_lite::ConnectionConfig;
+    ///
+    /// let conninfo = ConnectionConfig::new("db.internal", 5432)
+    ///     .options("-c search_path=it's")
+    ///     .to_conninfo();
+    ///
+    /// assert!(conninfo.starts_with("host='db.internal' port='5432'"));
+    /// assert!(conninfo.contains(r"options='-c search_path=it\'s'"));
+    /// ```
+    pub fn to_conninfo(&self) -> String {
+        self.conninfo_pairs()
+            .iter()
+            .map(|(keyword, value)| format!("{}={}", keyword, quote_conninfo_value(value)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Builds the connection string with the password masked, for logging.
+    pub fn to_redacted_conninfo(&self) -> String {
+        self.conninfo_pairs()
+            .iter()
+            .map(|(keyword, value)| {
+                if *keyword == "password" || *keyword == "sslpassword" {
+                    format!("{}=********", keyword)
+                } else {
+                    format!("{}={}", keyword, quote_conninfo_value(value))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl fmt::Debug for ConnectionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionConfig")
+            .field("conninfo", &self.to_redacted_conninfo())
+            .field("verbosity", &self.verbosity)
+            .field("persistent", &self.persistent)
+            .field("query_timeout", &self.query_timeout)
+            .field("statement_cache_size", &self.statement_cache_size)
+            .field("retry_policy", &self.retry_policy)
+            .finish()
+    }
+}
+
+// Shuffles `items` in place (Fisher-Yates). Spreading connections over hosts does
+// not need a strong RNG, so the random numbers come from std: every `RandomState`
+// is keyed with fresh random keys, and hashing the position with them gives an
+// unpredictable value per swap. The modulo bias is negligible for host lists.
+fn shuffle<T>(items: &mut [T]) {
+    let keys = RandomState::new();
+    for i in (1..items.len()).rev() {
+        let mut hasher = keys.build_hasher();
+        hasher.write_usize(i);
+        let j = (hasher.finish() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+// Escape spaces and backslashes, which libpq otherwise treats as separators in `options`
+fn escape_option_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == ' ' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Quote a value for use in a libpq keyword/value connection string
+fn quote_conninfo_value(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for c in value.chars() {
+        if c == '\'' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_sent_as_options() {
+        let config = ConnectionConfig::new("localhost", 5432)
+            .options("-c geqo=off")
+            .setting("search_path", "inventory, public")
+            .setting("work_mem", "64MB");
+        assert!(config.to_conninfo().contains(
+            r"options='-c geqo=off -c search_path=inventory,\\ public -c work_mem=64MB'"
+        ));
+    }
+
+    #[test]
+    fn setting_values_escape_spaces_and_backslashes() {
+        assert_eq!(escape_option_value("a b"), r"a\ b");
+        assert_eq!(escape_option_value(r"C:\temp"), r"C:\\temp");
+        assert_eq!(escape_option_value("plain"), "plain");
+
+        // Each escape is escaped again when the options value is quoted
+        let config = ConnectionConfig::new("localhost", 5432).setting("application_name", r"a\b c");
+        assert!(config
+            .to_conninfo()
+            .contains(r"options='-c application_name=a\\\\b\\ c'"));
+    }
+
+    #[test]
+    fn no_options_without_settings() {
+        let config = ConnectionConfig::new("localhost", 5432).init_statement("SET x = 1");
+        assert!(!config.to_conninfo().contains("options="));
+        assert_eq!(config.get_init_statements(), ["SET x = 1".to_string()]);
+    }
+
+    #[test]
+    fn conninfo_values_are_quoted_and_escaped() {
+        assert_eq!(quote_conninfo_value("plain"), "'plain'");
+        assert_eq!(quote_conninfo_value("two words"), "'two words'");
+        assert_eq!(quote_conninfo_value("it's"), r"'it\'s'");
+        assert_eq!(quote_conninfo_value(r"C:\certs"), r"'C:\\certs'");
+        assert_eq!(quote_conninfo_value(""), "''");
+
+        let config = ConnectionConfig::new("localhost", 5432)
+            .user("o'brien")
+            .password(r"p@ss word\1");
+        let conninfo = config.to_conninfo();
+        assert!(conninfo.contains(r"user='o\'brien'"));
+        assert!(conninfo.contains(r"password='p@ss word\\1'"));
+    }
+
+    #[test]
+    fn redacted_conninfo_masks_passwords() {
+        let config = ConnectionConfig::new("localhost", 5432)
+            .user("stackql")
+            .password("s3cret")
+            .tls(
+                TlsConfig::new(SslMode::Require)
+                    .client_cert("client.pem")
+                    .client_key("client.key")
+                    .key_password("k3y"),
+            );
+        let redacted = config.to_redacted_conninfo();
+        assert!(redacted.contains("password=********"));
+        assert!(redacted.contains("sslpassword=********"));
+        assert!(redacted.contains("user='stackql'"));
+        assert!(!redacted.contains("s3cret"));
+        assert!(!redacted.contains("k3y"));
+        assert!(!format!("{:?}", config).contains("s3cret"));
+    }
+
+    #[test]
+    fn validate_rejects_channel_binding_without_tls() {
+        let config = ConnectionConfig::new("localhost", 5432)
+            .tls(TlsConfig::new(SslMode::Disable))
+            .channel_binding(ChannelBinding::Require);
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("channel_binding=require"));
+
+        assert!(ConnectionConfig::new("localhost", 5432)
+            .tls(TlsConfig::new(SslMode::Require))
+            .channel_binding(ChannelBinding::Require)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_requirepeer_on_tcp_hosts() {
+        let config = ConnectionConfig::new("localhost", 5432).requirepeer("postgres");
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("requirepeer"));
+
+        assert!(
+            ConnectionConfig::from_unix_socket("/var/run/postgresql", 5432)
+                .requirepeer("postgres")
+                .validate()
+                .is_ok()
+        );
+        assert!(ConnectionConfig::new("localhost", 5432)
+            .add_unix_socket("/var/run/postgresql", 5432)
+            .requirepeer("postgres")
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn shuffle_is_a_permutation() {
+        let mut items: Vec<u32> = (0..32).collect();
+        shuffle(&mut items);
+        let mut sorted = items.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..32).collect::<Vec<_>>());
+
+        // Ten shuffles of 32 items all leaving them in order would not be random
+        let shuffled = (0..10).any(|_| {
+            let mut items: Vec<u32> = (0..32).collect();
+            shuffle(&mut items);
+            items != (0..32).collect::<Vec<_>>()
+        });
+        assert!(shuffled);
+
+        let mut empty: Vec<u32> = Vec::new();
+        shuffle(&mut empty);
+        let mut single = vec![1];
+        shuffle(&mut single);
+        assert_eq!(single, [1]);
+    }
+
+    #[test]
+    fn shuffled_hosts_keep_their_ports() {
+        let config = (1..8)
+            .fold(ConnectionConfig::new("db0", 5430), |config, i| {
+                config.add_host(&format!("db{}", i), 5430 + i)
+            })
+            .load_balance_hosts(LoadBalanceHosts::Random);
+
+        let shuffled = config.with_shuffled_hosts();
+        assert_eq!(shuffled.get_load_balance_hosts(), None);
+        assert_eq!(shuffled.get_hosts().len(), 8);
+        for host in shuffled.get_hosts() {
+            let index: u16 = host.host.trim_start_matches("db").parse().unwrap();
+            assert_eq!(host.port, 5430 + index);
+        }
+        let mut names: Vec<&str> = shuffled
+            .get_hosts()
+            .iter()
+            .map(|h| h.host.as_str())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            ["db0", "db1", "db2", "db3", "db4", "db5", "db6", "db7"]
+        );
+    }
+
+    #[test]
+    fn service_configurations_leave_the_host_to_the_service() {
+        let conninfo = ConnectionConfig::from_service("stackql")
+            .passfile("/home/app/.pgpass")
+            .to_conninfo();
+        assert!(conninfo.starts_with("service='stackql' "));
+        assert!(!conninfo.contains("host="));
+        assert!(!conninfo.contains("port="));
+        assert!(conninfo.contains("passfile='/home/app/.pgpass'"));
+
+        // A host set explicitly overrides the one in the service entry
+        let config = ConnectionConfig::from_service("stackql").add_host("db.internal", 5433);
+        assert!(config
+            .to_conninfo()
+            .contains("host='db.internal' port='5433'"));
+    }
+
+    #[test]
+    fn channel_binding_round_trips_through_its_libpq_name() {
+        for (binding, name) in [
+            (ChannelBinding::Disable, "disable"),
+            (ChannelBinding::Prefer, "prefer"),
+            (ChannelBinding::Require, "require"),
+        ] {
+            assert_eq!(binding.as_str(), name);
+            assert_eq!(name.parse::<ChannelBinding>(), Ok(binding));
+            let config = ConnectionConfig::new("localhost", 5432).channel_binding(binding);
+            assert!(config
+                .to_conninfo()
+                .contains(&format!("channel_binding='{}'", name)));
+        }
+        assert!("required".parse::<ChannelBinding>().is_err());
+    }
+
+    #[test]
+    fn query_timeout_is_enforced_by_the_client() {
+        let config = ConnectionConfig::new("localhost", 5432);
+        assert_eq!(config.get_query_timeout(), None);
+
+        let config = config.query_timeout(Duration::from_secs(5));
+        assert_eq!(config.get_query_timeout(), Some(Duration::from_secs(5)));
+        assert!(!config.to_conninfo().contains("statement_timeout"));
+    }
+
+    #[test]
+    fn hosts_are_classified_by_their_first_character() {
+        let tcp = Host::tcp("db.internal", 5432);
+        assert!(!tcp.is_unix_socket());
+        assert!(!tcp.is_abstract_socket());
+        assert_eq!(tcp.socket_path(), None);
+
+        let socket = Host::unix_socket("/var/run/postgresql/", 5433);
+        assert!(socket.is_unix_socket());
+        assert!(!socket.is_abstract_socket());
+        assert_eq!(
+            socket.socket_path().as_deref(),
+            Some("/var/run/postgresql/.s.PGSQL.5433")
+        );
+
+        let abstract_socket = Host::abstract_socket("pgsocket", 5432);
+        assert_eq!(abstract_socket.host, "@pgsocket");
+        assert!(abstract_socket.is_unix_socket());
+        assert!(abstract_socket.is_abstract_socket());
+        assert_eq!(abstract_socket.to_string(), "@pgsocket/.s.PGSQL.5432");
+    }
+
+    #[test]
+    fn hosts_display_as_addresses() {
+        assert_eq!(Host::tcp("localhost", 5432).to_string(), "localhost:5432");
+        assert_eq!(Host::tcp("::1", 5432).to_string(), "[::1]:5432");
+        assert_eq!(
+            Host::unix_socket("/tmp", 5432).to_string(),
+            "/tmp/.s.PGSQL.5432"
+        );
+    }
+
+    #[test]
+    fn socket_hosts_are_rendered_as_directories() {
+        let conninfo = ConnectionConfig::from_unix_socket("/var/run/postgresql", 5432)
+            .add_abstract_socket("pgsocket", 5433)
+            .add_host("db.internal", 5434)
+            .requirepeer("postgres")
+            .to_conninfo();
+        assert!(conninfo
+            .contains("host='/var/run/postgresql,@pgsocket,db.internal' port='5432,5433,5434'"));
+        assert!(conninfo.contains("requirepeer='postgres'"));
+    }
+}
@@ -1,21 +1,31 @@
 // src/connection.rs
 
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::ffi::{c_void, CStr};
-use std::sync::Arc;
-use std::time::Instant;
+use std::ffi::CString;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
 
 use log::debug;
 
-use libpq::Connection;
-use libpq_sys::ExecStatusType::{PGRES_COMMAND_OK, PGRES_TUPLES_OK};
-use libpq_sys::{
-    PGContextVisibility, PQclear, PQconsumeInput, PQfname, PQgetResult, PQgetvalue, PQlibVersion,
-    PQnfields, PQntuples, PQresultStatus, PQresultVerboseErrorMessage, PQsendQuery,
-    PQsetErrorVerbosity, PQsetNoticeReceiver,
-};
+use libpq_sys::PGPing::{PQPING_NO_ATTEMPT, PQPING_NO_RESPONSE, PQPING_OK, PQPING_REJECT};
+use libpq_sys::{PQlibVersion, PQping};
 
-use crate::notices::{notice_receiver, Notice, NoticeStorage, Verbosity};
+use crate::cancel::{CancelState, CancelToken};
+use crate::config::{ConnectionConfig, Host};
+use crate::copy::{CopyIn, CopyOutIter};
+use crate::notices::{Notice, Verbosity};
+use crate::params::{Oid, ToSql};
+use crate::pipeline::{Pipeline, PipelineResult};
+use crate::rows::{RowIter, SessionRef};
+use crate::server_info::ServerInfo;
+use crate::session::{self, Session};
+use crate::statement::{check_statement_name, Statement};
+use crate::tls::TlsInfo;
 use crate::value::Value;
 
 /// Main client for interacting with PostgreSQL-compatible servers.
@@ -23,11 +33,12 @@ use crate::value::Value;
 /// This struct provides the core functionality for establishing connections
 /// and executing queries against a PostgreSQL-compatible server.
 pub struct PgwireLite {
-    hostname: String,
-    port: u16,
-    use_tls: bool,
+    config: ConnectionConfig,
     verbosity: Verbosity,
-    notices: NoticeStorage,
+    session: Mutex<Option<Session>>,
+    // The thread holding the persistent connection for a row iterator or copy
+    stream_owner: Mutex<Option<ThreadId>>,
+    cancel: Arc<CancelState>,
 }
 
 /// Contains the complete result of a query execution.
@@ -59,16 +70,107 @@ pub struct QueryResult {
 
     /// Elapsed time for the query execution in milliseconds.
     pub elapsed_time_ms: u64,
+
+    /// Number of attempts made to execute the query, including the successful one.
+    pub attempts: u32,
+
+    /// The server from the host list that executed the query.
+    pub server: Host,
+
+    /// TLS session details of the connection the query ran on, or `None` if TLS was not used.
+    pub tls: Option<TlsInfo>,
 }
 
-// Helper function to safely clear a PGresult and log it
-fn clear_pg_result(result: *mut libpq_sys::PGresult) {
-    if !result.is_null() {
-        unsafe {
-            debug!("Clearing PGresult at {:p}", result);
-            PQclear(result);
-            debug!("PGresult cleared successfully");
-        }
+/// The result of one statement run by [`PgwireLite::query_multi`].
+#[derive(Debug)]
+pub struct StatementResult {
+    /// Rows returned by the statement, represented as maps of column names to values.
+    pub rows: Vec<HashMap<String, Value>>,
+
+    /// Names of the columns in the result set.
+    pub column_names: Vec<String>,
+
+    /// Notices generated while the statement ran.
+    ///
+    /// Notices are assigned as libpq reads them, so a notice raised at the very
+    /// start of the next statement can be reported here when both arrive together.
+    pub notices: Vec<Notice>,
+
+    /// Number of rows in the result set.
+    pub row_count: i32,
+
+    /// Number of columns in the result set.
+    pub col_count: i32,
+
+    /// Status of the statement execution.
+    pub status: libpq_sys::ExecStatusType,
+
+    /// The command tag reported by the server, e.g. `SELECT 2` or `INSERT 0 1`.
+    pub command_tag: String,
+
+    /// Number of rows the statement returned or affected, for commands that report one.
+    pub rows_affected: Option<u64>,
+
+    /// Time from the previous statement's completion until this statement's
+    /// result was received, in milliseconds.
+    pub elapsed_time_ms: u64,
+}
+
+/// The parameters and result columns of a statement, returned by
+/// [`PgwireLite::describe`] without executing the statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementDescription {
+    /// Type OIDs of the statement's parameters `$1`, `$2`, ..., as inferred by the server.
+    pub param_types: Vec<Oid>,
+
+    /// The columns the statement returns, empty for statements without a result set.
+    pub columns: Vec<ColumnDescription>,
+}
+
+/// Metadata of one result column in a [`StatementDescription`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnDescription {
+    /// Name of the column.
+    pub name: String,
+
+    /// OID of the column's data type.
+    pub type_oid: Oid,
+
+    /// Type modifier, such as the length of a `varchar(n)`; `-1` if not applicable.
+    pub type_modifier: i32,
+
+    /// Size of the type in bytes, or a negative value for variable-length types.
+    pub type_size: i32,
+
+    /// OID of the table the column was taken from, if it is a plain table column.
+    pub table_oid: Option<Oid>,
+
+    /// Column number within that table, starting from 1.
+    pub table_column: Option<i32>,
+}
+
+/// Outcome of a [`PgwireLite::ping`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingStatus {
+    /// The server is running and accepting connections.
+    Ok,
+    /// The server is running but not accepting connections, e.g. while starting up or shutting down.
+    Rejecting,
+    /// The server could not be contacted.
+    NoResponse,
+    /// No attempt was made to contact the server, because the connection parameters are invalid.
+    NoAttempt,
+}
+
+impl fmt::Display for PingStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            PingStatus::Ok => "accepting connections",
+            PingStatus::Rejecting => "rejecting connections",
+            PingStatus::NoResponse => "no response",
+            PingStatus::NoAttempt => "no attempt made",
+        };
+        write!(f, "{}", status)
     }
 }
 
@@ -109,6 +211,41 @@ impl PgwireLite {
             _ => Verbosity::Default,
         };
 
+        let config = ConnectionConfig::new(hostname, port)
+            .use_tls(use_tls)
+            .verbosity(verbosity_val);
+
+        Self::from_config(config)
+    }
+
+    /// Creates a new PgwireLite client from a [`ConnectionConfig`].
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The connection settings used for every connection the client opens
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the new PgwireLite instance or an error
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use pgwire_lite::{ConnectionConfig, PgwireLite, Verbosity};
+    ///
+    /// let config = ConnectionConfig::new("localhost", 5432)
+    ///     .user("postgres")
+    ///     .dbname("postgres")
+    ///     .verbosity(Verbosity::Verbose);
+    ///
+    /// let client = PgwireLite::from_config(config)
+    ///     .expect("Failed to create client");
+    /// ```
+    pub fn from_config(config: ConnectionConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        config.validate()?;
+
+        let verbosity_val = config.get_verbosity();
+
         // Set the log filter level based on verbosity
         match verbosity_val {
             Verbosity::Terse => log::set_max_level(log::LevelFilter::Warn),
@@ -117,17 +254,52 @@ impl PgwireLite {
             Verbosity::Sqlstate => log::set_max_level(log::LevelFilter::Debug),
         }
 
-        let notices = Arc::new(std::sync::Mutex::new(Vec::new()));
-
         Ok(PgwireLite {
-            hostname: hostname.to_string(),
-            port,
-            use_tls,
+            config,
             verbosity: verbosity_val,
-            notices,
+            session: Mutex::new(None),
+            stream_owner: Mutex::new(None),
+            cancel: Arc::new(CancelState::default()),
         })
     }
 
+    /// Creates a new PgwireLite client from a libpq connection string.
+    ///
+    /// Accepts both `postgresql://` URIs and `keyword=value` strings; see
+    /// [`dsn::parse`](crate::dsn::parse) for the supported syntax. The string is
+    /// validated up front and a [`DsnError`](crate::DsnError) describing the
+    /// problem and its position is returned if it cannot be parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `dsn` - The connection URI or keyword/value string
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the new PgwireLite instance or an error
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use pgwire_lite::PgwireLite;
+    ///
+    /// let client = PgwireLite::from_dsn("postgresql://localhost:5444?application_name=reports")
+    ///     .expect("Failed to create client");
+    ///
+    /// let client: PgwireLite = "host=localhost port=5444 connect_timeout=5"
+    ///     .parse()
+    ///     .expect("Failed to create client");
+    /// ```
+    pub fn from_dsn(dsn: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = dsn.parse::<ConnectionConfig>()?;
+        Self::from_config(config)
+    }
+
+    /// Returns the connection settings used by this client.
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
     /// Returns the version of the underlying libpq library.
     ///
     /// # Returns
@@ -150,29 +322,17 @@ impl PgwireLite {
         format!("{:?}", self.verbosity)
     }
 
-    // Helper method to consume any pending results
-    fn consume_pending_results(conn: &Connection) {
-        debug!("Consuming pending results");
-        unsafe {
-            // First make sure we've read all data available from the server
-            PQconsumeInput(conn.into());
-
-            // Then clear any pending results
-            loop {
-                let result = PQgetResult(conn.into());
-                if result.is_null() {
-                    break;
-                }
-                clear_pg_result(result);
-            }
-        }
-    }
-
     /// Executes a SQL query and returns the results.
     ///
-    /// This method creates a fresh connection for each query, executes the query,
-    /// and processes the results. It handles all aspects of connection management
-    /// and error handling.
+    /// If the client has a [`RetryPolicy`](crate::RetryPolicy), failures to open
+    /// the connection are retried; use [`PgwireLite::query_idempotent`] to also
+    /// retry failures during execution.
+    ///
+    /// By default this method creates a fresh connection for each query, executes the
+    /// query, and processes the results. When the client was configured with
+    /// [`ConnectionConfig::persistent`], the query runs on the client's long-lived
+    /// connection instead, which is reopened automatically if it was closed or broken.
+    /// It handles all aspects of connection management and error handling.
     ///
     /// # Arguments
     ///
@@ -196,210 +356,819 @@ impl PgwireLite {
     /// println!("Number of rows: {}", result.row_count);
     /// ```
     pub fn query(&self, query: &str) -> Result<QueryResult, Box<dyn std::error::Error>> {
-        // Clear any previous notices
-        debug!("Clearing previous notices");
-        if let Ok(mut notices) = self.notices.lock() {
-            notices.clear();
-        }
+        self.run_query(query, self.config.get_query_timeout())
+    }
 
-        let start_time = Instant::now();
-
-        // Create a connection string
-        let conn_str = format!(
-            "host={} port={} sslmode={} application_name=pgwire-lite-client connect_timeout=10 client_encoding=UTF8",
-            self.hostname,
-            self.port,
-            if self.use_tls { "verify-full" } else { "disable" }
-        );
-        debug!("Establishing connection using: {}", conn_str);
-
-        // Create a fresh connection for this query
-        let conn = Connection::new(&conn_str)?;
-
-        // Connection diagnostics
-        unsafe {
-            let ssl_in_use = libpq_sys::PQsslInUse((&conn).into()) != 0;
-            let host_ptr = libpq_sys::PQhost((&conn).into());
-            let port_ptr = libpq_sys::PQport((&conn).into());
-            if !host_ptr.is_null() && !port_ptr.is_null() {
-                let host = CStr::from_ptr(host_ptr).to_string_lossy();
-                let port = CStr::from_ptr(port_ptr).to_string_lossy();
-                debug!("Connected to: {}:{} (ssl: {})", host, port, ssl_in_use);
-            }
+    /// Executes a SQL query with a time limit and returns the results.
+    ///
+    /// Behaves like [`PgwireLite::query`], but overrides the client's default
+    /// [`ConnectionConfig::query_timeout`]. If the query has not completed when the
+    /// limit is reached it is cancelled, and a [`QueryTimeout`](crate::QueryTimeout)
+    /// error reports how long it ran and whether the server acknowledged the cancel.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use pgwire_lite::{PgwireLite, QueryTimeout};
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// match client.query_with_timeout("SELECT * FROM aws.ec2.instances", Duration::from_secs(60)) {
+    ///     Ok(result) => println!("{} rows", result.row_count),
+    ///     Err(e) => match e.downcast_ref::<QueryTimeout>() {
+    ///         Some(timeout) => println!("Gave up after {:?}", timeout.elapsed),
+    ///         None => println!("Query failed: {}", e),
+    ///     },
+    /// }
+    /// ```
+    pub fn query_with_timeout(
+        &self,
+        query: &str,
+        timeout: Duration,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        self.run_query(query, Some(timeout))
+    }
 
-            // PQstatus output
-            let status = libpq_sys::PQstatus((&conn).into());
-            debug!("Connection status: {:?}", status);
-
-            // PQtransactionStatus output
-            let tx_status = libpq_sys::PQtransactionStatus((&conn).into());
-            debug!("Transaction status: {:?}", tx_status);
-
-            // PQserverVersion output
-            let server_version = libpq_sys::PQserverVersion((&conn).into());
-            let major = server_version / 10000;
-            let minor = (server_version / 100) % 100;
-            let revision = server_version % 100;
-            debug!(
-                "Server version: {}.{}.{} ({})",
-                major, minor, revision, server_version
-            );
-        }
+    /// Executes an idempotent SQL query and returns the results.
+    ///
+    /// Behaves like [`PgwireLite::query`], but marks the statement as safe to run
+    /// more than once, so the client's [`RetryPolicy`](crate::RetryPolicy) also
+    /// retries failures that happen after the statement was sent, such as
+    /// serialization failures or a connection lost mid-query. Only use it for
+    /// statements whose repeated execution has the same effect as running them once.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::{ConnectionConfig, PgwireLite, RetryPolicy};
+    ///
+    /// let client = PgwireLite::from_config(
+    ///     ConnectionConfig::new("localhost", 5444).retry_policy(RetryPolicy::new().max_attempts(5)),
+    /// )
+    /// .expect("Failed to create client");
+    ///
+    /// let result = client
+    ///     .query_idempotent("SELECT region, name FROM aws.ec2.instances WHERE region = 'us-east-1'")
+    ///     .expect("Query failed");
+    /// println!("{} rows after {} attempt(s)", result.row_count, result.attempts);
+    /// ```
+    pub fn query_idempotent(&self, query: &str) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        self.run_query_with_retries(query, self.config.get_query_timeout(), true)
+    }
 
-        // Apply the desired verbosity level
-        debug!("Setting error verbosity to: {:?}", self.verbosity);
-        unsafe {
-            PQsetErrorVerbosity((&conn).into(), self.verbosity.into());
-        }
+    /// Executes a SQL query and returns an iterator over its rows as they arrive.
+    ///
+    /// Unlike [`PgwireLite::query`], rows are not collected in memory first, so
+    /// arbitrarily large results can be processed row by row. Rows are fetched in
+    /// chunks with libpq 17 or later and one at a time with older versions. The
+    /// client's query timeout applies to each wait for rows from the server, and a
+    /// [`CancelToken`] cancels the query while it is being read. Only a single
+    /// statement is allowed.
+    ///
+    /// The iterator keeps its connection until it is dropped; in persistent mode
+    /// queries from other threads wait until then, while other calls on this client
+    /// from the same thread fail with a "session busy" error. Dropping it before
+    /// reading every row cancels the rest of the query.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::PgwireLite;
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// let rows = client
+    ///     .query_iter("SELECT instance_id, region FROM aws.ec2.instances")
+    ///     .expect("Query failed");
+    /// for row in rows {
+    ///     let row = row.expect("Failed to read row");
+    ///     println!("{:?}", row.get("instance_id"));
+    /// }
+    /// ```
+    pub fn query_iter(&self, query: &str) -> Result<RowIter<'_>, Box<dyn std::error::Error>> {
+        let session = if self.config.get_persistent() {
+            SessionRef::persistent(self.persistent_session()?, &self.stream_owner)
+        } else {
+            debug!("Opening a connection for this query");
+            SessionRef::Owned(Box::new(Session::connect(&self.config)?))
+        };
+        RowIter::start(
+            session,
+            query,
+            self.config.get_query_timeout(),
+            Arc::clone(&self.cancel),
+        )
+    }
 
-        // Set up notice receiver for the connection
-        debug!("Setting up notice receiver");
-        let notices_ptr = Arc::into_raw(self.notices.clone()) as *mut c_void;
-        unsafe {
-            PQsetNoticeReceiver((&conn).into(), Some(notice_receiver), notices_ptr);
-        }
+    /// Starts a `COPY ... FROM STDIN` statement and returns a writer for its data.
+    ///
+    /// The returned [`CopyIn`] accepts raw bytes in whatever format the statement
+    /// names, the contents of a [`std::io::Read`], or rows of [`Value`]s.
+    /// [`CopyIn::finish`] completes the copy and returns the number of rows loaded.
+    /// The client's query timeout applies to starting and finishing the copy, but
+    /// not to writing the data. Copies are never retried.
+    ///
+    /// The writer keeps its connection until it is dropped; in persistent mode
+    /// queries from other threads wait until then, while other calls on this client
+    /// from the same thread fail with a "session busy" error.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::{CopyFormat, PgwireLite, Value};
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// let mut writer = client
+    ///     .copy_in("COPY audit (id, event) FROM STDIN (FORMAT csv)")
+    ///     .expect("COPY failed")
+    ///     .format(CopyFormat::Csv);
+    /// writer
+    ///     .write_row(&[Value::Integer(1), Value::String("login".to_string())])
+    ///     .expect("Failed to write row");
+    /// let rows = writer.finish().expect("COPY failed");
+    /// println!("{} rows loaded", rows);
+    /// ```
+    pub fn copy_in(&self, query: &str) -> Result<CopyIn<'_>, Box<dyn std::error::Error>> {
+        let session = if self.config.get_persistent() {
+            SessionRef::persistent(self.persistent_session()?, &self.stream_owner)
+        } else {
+            debug!("Opening a connection for this query");
+            SessionRef::Owned(Box::new(Session::connect(&self.config)?))
+        };
+        CopyIn::start(
+            session,
+            query,
+            self.config.get_query_timeout(),
+            Arc::clone(&self.cancel),
+        )
+    }
 
-        // add ; to `query` if it doesn't end with one
-        let query = if query.ends_with(';') {
-            query.to_string()
+    /// Runs a `COPY ... TO STDOUT` statement and writes its output to `writer` as
+    /// it arrives; returns the number of rows copied.
+    ///
+    /// Output in any format, including binary, is passed through unchanged one row
+    /// at a time, so memory use does not grow with the size of the table. The
+    /// client's query timeout applies to each wait for output from the server, and
+    /// a [`CancelToken`] cancels the copy. A failing writer stops the copy. Only
+    /// connection failures before the statement is sent are retried.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    ///
+    /// use pgwire_lite::PgwireLite;
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// let file = File::create("audit.csv").expect("Failed to create file");
+    /// let rows = client
+    ///     .copy_out("COPY audit TO STDOUT (FORMAT csv, HEADER)", file)
+    ///     .expect("COPY failed");
+    /// println!("{} rows exported", rows);
+    /// ```
+    pub fn copy_out<W: Write>(
+        &self,
+        query: &str,
+        writer: W,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let timeout = self.config.get_query_timeout();
+        let writer = RefCell::new(writer);
+        self.run_with_retries(false, |session| {
+            session.copy_out(query, &mut *writer.borrow_mut(), timeout)
+        })
+        .map(|(rows, _)| rows)
+    }
+
+    /// Runs a `COPY ... TO STDOUT` statement and returns an iterator over its rows,
+    /// parsed into [`Value`]s as they arrive.
+    ///
+    /// Rows are parsed from text format unless [`CopyOutIter::format`] selects CSV;
+    /// binary output is not supported. The client's query timeout applies to each
+    /// wait for rows from the server, and a [`CancelToken`] cancels the copy.
+    ///
+    /// The iterator keeps its connection until it is dropped; in persistent mode
+    /// queries from other threads wait until then, while other calls on this client
+    /// from the same thread fail with a "session busy" error. Dropping it before
+    /// reading every row cancels the rest of the copy.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::PgwireLite;
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// let rows = client
+    ///     .copy_out_iter("COPY audit (id, event) TO STDOUT")
+    ///     .expect("COPY failed");
+    /// for row in rows {
+    ///     let row = row.expect("Failed to read row");
+    ///     println!("{:?}", row);
+    /// }
+    /// ```
+    pub fn copy_out_iter(
+        &self,
+        query: &str,
+    ) -> Result<CopyOutIter<'_>, Box<dyn std::error::Error>> {
+        let session = if self.config.get_persistent() {
+            SessionRef::persistent(self.persistent_session()?, &self.stream_owner)
         } else {
-            format!("{};", query)
+            debug!("Opening a connection for this query");
+            SessionRef::Owned(Box::new(Session::connect(&self.config)?))
         };
+        CopyOutIter::start(
+            session,
+            query,
+            self.config.get_query_timeout(),
+            Arc::clone(&self.cancel),
+        )
+    }
+
+    /// Executes a SQL query with parameters and returns the results.
+    ///
+    /// Parameters are referenced in the query as `$1`, `$2` and so on, and are sent
+    /// separately from the query text using the extended query protocol, so values
+    /// never need to be quoted or escaped. Each parameter is sent with the type OID
+    /// of its [`ToSql`](crate::ToSql) implementation. Only a single statement is
+    /// allowed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::PgwireLite;
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// let region = "us-east-1";
+    /// let result = client
+    ///     .query_params(
+    ///         "SELECT instance_id FROM aws.ec2.instances WHERE region = $1 LIMIT $2",
+    ///         &[&region, &10i64],
+    ///     )
+    ///     .expect("Query failed");
+    /// println!("{} rows", result.row_count);
+    /// ```
+    pub fn query_params(
+        &self,
+        query: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        let timeout = self.config.get_query_timeout();
+        // Only a persistent connection lives long enough to reuse prepared statements
+        let cached = self.config.get_persistent() && self.config.get_statement_cache_size() > 0;
+        let (mut result, attempts) = self.run_with_retries(false, |session| {
+            if cached {
+                session.query_cached(query, params, timeout)
+            } else {
+                session.query_params(query, params, timeout)
+            }
+        })?;
+        result.attempts = attempts;
+        Ok(result)
+    }
+
+    /// Prepares a named statement on the server and returns it for use with
+    /// [`PgwireLite::execute_prepared`].
+    ///
+    /// The SQL is parsed and checked by the server now, so syntax errors and unknown
+    /// tables are reported here. Parameter types are inferred from the statement.
+    /// The statement is prepared again automatically on any connection that does not
+    /// have it, so it keeps working after a reconnect; without persistent mode each
+    /// execution opens a new connection and prepares it again.
+    ///
+    /// Names starting with `__pgwire_lite_` are reserved for cached statements and
+    /// are rejected.
+    ///
+    /// Queries with parameters run through [`PgwireLite::query_params`] are also
+    /// prepared and cached automatically on persistent connections; see
+    /// [`ConnectionConfig::statement_cache_size`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::{ConnectionConfig, PgwireLite};
+    ///
+    /// let client = PgwireLite::from_config(ConnectionConfig::new("localhost", 5444).persistent(true))
+    ///     .expect("Failed to create client");
+    ///
+    /// let statement = client
+    ///     .prepare("instances_by_region", "SELECT instance_id FROM aws.ec2.instances WHERE region = $1")
+    ///     .expect("Prepare failed");
+    ///
+    /// for region in ["us-east-1", "eu-west-1"] {
+    ///     let result = client.execute_prepared(&statement, &[&region]).expect("Query failed");
+    ///     println!("{}: {} instances", region, result.row_count);
+    /// }
+    /// ```
+    pub fn prepare(
+        &self,
+        name: &str,
+        query: &str,
+    ) -> Result<Statement, Box<dyn std::error::Error>> {
+        check_statement_name(name)?;
+        let statement = Statement::new(name, query);
+        self.with_session(|session| session.prepare(&statement))?;
+        Ok(statement)
+    }
+
+    /// Executes a statement returned by [`PgwireLite::prepare`] with the given
+    /// parameters and returns the results.
+    pub fn execute_prepared(
+        &self,
+        statement: &Statement,
+        params: &[&dyn ToSql],
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        let timeout = self.config.get_query_timeout();
+        let (mut result, attempts) = self.run_with_retries(false, |session| {
+            session.query_prepared(statement, params, timeout)
+        })?;
+        result.attempts = attempts;
+        Ok(result)
+    }
+
+    /// Describes a SQL statement without executing it.
+    ///
+    /// The statement is parsed and analysed by the server, which reports the types
+    /// it infers for the parameters and the columns the statement would return. Use
+    /// it to inspect an expensive query before running it; errors such as unknown
+    /// tables are reported as for [`PgwireLite::query`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::PgwireLite;
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// let description = client
+    ///     .describe("SELECT instance_id, launch_time FROM aws.ec2.instances WHERE region = $1")
+    ///     .expect("Describe failed");
+    /// for column in &description.columns {
+    ///     println!("{} (type {})", column.name, column.type_oid);
+    /// }
+    /// ```
+    pub fn describe(
+        &self,
+        query: &str,
+    ) -> Result<StatementDescription, Box<dyn std::error::Error>> {
+        self.with_session(|session| session.describe(query))
+    }
+
+    /// Describes a statement returned by [`PgwireLite::prepare`].
+    pub fn describe_prepared(
+        &self,
+        statement: &Statement,
+    ) -> Result<StatementDescription, Box<dyn std::error::Error>> {
+        self.with_session(|session| session.describe_prepared(statement))
+    }
+
+    /// Describes the result columns of an open cursor (portal).
+    ///
+    /// Cursors only exist within the transaction on the connection that declared
+    /// them, so this requires persistent mode.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::{ConnectionConfig, PgwireLite};
+    ///
+    /// let client = PgwireLite::from_config(ConnectionConfig::new("localhost", 5444).persistent(true))
+    ///     .expect("Failed to create client");
+    ///
+    /// client.query("BEGIN").expect("Query failed");
+    /// client
+    ///     .query("DECLARE instances CURSOR FOR SELECT * FROM aws.ec2.instances")
+    ///     .expect("Query failed");
+    /// let description = client.describe_portal("instances").expect("Describe failed");
+    /// println!("{} columns", description.columns.len());
+    /// ```
+    pub fn describe_portal(
+        &self,
+        portal: &str,
+    ) -> Result<StatementDescription, Box<dyn std::error::Error>> {
+        self.with_session(|session| session.describe_portal(portal))
+    }
+
+    /// Executes a string of several SQL statements, separated by semicolons, and
+    /// returns one result per statement.
+    ///
+    /// Unlike [`PgwireLite::query`], which only returns the first result, every
+    /// statement's rows, command tag and notices are returned in order. Execution
+    /// stops at the first failing statement, which is reported as a
+    /// [`StatementError`](crate::StatementError) carrying its zero-based index and
+    /// the results of the statements before it.
+    ///
+    /// Unless the string contains explicit transaction control, the server runs all
+    /// statements in a single implicit transaction, so a failure also rolls back the
+    /// statements that completed before it. The client's query timeout applies to the
+    /// whole string, and only connection failures are retried.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::{PgwireLite, StatementError};
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// match client.query_multi("SELECT 1 AS a; SELECT 2 AS b; SELECT 1/0") {
+    ///     Ok(results) => {
+    ///         for result in results {
+    ///             println!("{}: {} rows", result.command_tag, result.row_count);
+    ///         }
+    ///     }
+    ///     Err(e) => match e.downcast_ref::<StatementError>() {
+    ///         Some(failed) => println!("Statement {} failed: {}", failed.index, failed.error),
+    ///         None => println!("Query failed: {}", e),
+    ///     },
+    /// }
+    /// ```
+    pub fn query_multi(
+        &self,
+        query: &str,
+    ) -> Result<Vec<StatementResult>, Box<dyn std::error::Error>> {
+        let timeout = self.config.get_query_timeout();
+        self.run_with_retries(false, |session| session.query_multi(query, timeout))
+            .map(|(results, _)| results)
+    }
+
+    /// Sends all statements of a [`Pipeline`] to the server at once and returns one
+    /// [`PipelineResult`] per statement, in order.
+    ///
+    /// The statements are sent in libpq pipeline mode without waiting for earlier
+    /// results, which saves a network round trip per statement. This pays off most
+    /// with a persistent connection; otherwise the pipeline runs on a fresh
+    /// connection like any other query.
+    ///
+    /// A failing statement is reported as [`PipelineResult::Failed`] rather than as
+    /// an error. Errors are returned only when the pipeline as a whole could not run,
+    /// such as a lost connection, a cancellation or the client's query timeout,
+    /// which applies to the whole pipeline. Only connection failures are retried.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::{PgwireLite, Pipeline, PipelineResult};
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// let pipeline = Pipeline::new()
+    ///     .query("SELECT 1 AS a")
+    ///     .query("SELECT 1/0")
+    ///     .query_params("SELECT $1::int AS b", &[&2]);
+    ///
+    /// for (index, result) in client.execute_pipeline(&pipeline).unwrap().iter().enumerate() {
+    ///     match result {
+    ///         PipelineResult::Completed(result) => println!("{}: {} rows", index, result.row_count),
+    ///         PipelineResult::Failed(e) => println!("{}: failed: {}", index, e),
+    ///         PipelineResult::Aborted => println!("{}: aborted", index),
+    ///     }
+    /// }
+    /// ```
+    pub fn execute_pipeline(
+        &self,
+        pipeline: &Pipeline,
+    ) -> Result<Vec<PipelineResult>, Box<dyn std::error::Error>> {
+        let timeout = self.config.get_query_timeout();
+        self.run_with_retries(false, |session| session.execute_pipeline(pipeline, timeout))
+            .map(|(results, _)| results)
+    }
+
+    fn run_query(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        self.run_query_with_retries(query, timeout, false)
+    }
 
-        // Use PQsendQuery
-        debug!("Sending query: {}", query);
-        let send_success = unsafe { PQsendQuery((&conn).into(), query.as_ptr() as *const i8) };
-        if send_success == 0 {
-            // If send failed, return the error
-            return Err(
-                format!("Error: {}", conn.error_message().unwrap_or("Unknown error")).into(),
-            );
+    // Runs a query, retrying transient failures as allowed by the retry policy
+    fn run_query_with_retries(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+        idempotent: bool,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        let (mut result, attempts) =
+            self.run_with_retries(idempotent, |session| session.query(query, timeout))?;
+        result.attempts = attempts;
+        Ok(result)
+    }
+
+    // Runs a cancellable operation on a session, retrying transient failures as
+    // allowed by the retry policy; returns the result and the number of attempts
+    fn run_with_retries<T>(
+        &self,
+        idempotent: bool,
+        f: impl Fn(&Session) -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<(T, u32), Box<dyn std::error::Error>> {
+        let mut attempt = 1;
+        loop {
+            let result = self.with_session(|session| self.cancel.run(session, &f));
+            match result {
+                Ok(result) => return Ok((result, attempt)),
+                Err(e) => {
+                    let delay = self
+                        .config
+                        .get_retry_policy()
+                        .and_then(|policy| policy.retry_delay(attempt, e.as_ref(), idempotent));
+                    let Some(delay) = delay else {
+                        return Err(e);
+                    };
+                    debug!(
+                        "Attempt {} failed ({}), retrying in {:?}",
+                        attempt,
+                        e.to_string().trim_end(),
+                        delay
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+            }
         }
+    }
 
-        // Process the result
-        debug!("Processing the result");
-        let result = unsafe { PQgetResult((&conn).into()) };
+    /// Returns a token that cancels queries running on this client from another thread.
+    ///
+    /// The token can be obtained before a query starts and used while it runs; a
+    /// cancelled query fails with [`QueryCancelled`](crate::QueryCancelled). See
+    /// [`CancelToken`].
+    pub fn cancel_token(&self) -> CancelToken {
+        CancelToken::new(Arc::clone(&self.cancel))
+    }
 
-        if result.is_null() {
-            return Err("No result returned".into());
+    /// Checks whether the server is accepting connections.
+    ///
+    /// Uses `PQping`, which contacts the server without authenticating, so it works
+    /// even when the configured credentials are missing or wrong. It does not use or
+    /// affect the persistent connection.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::{PgwireLite, PingStatus};
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// if client.ping() == PingStatus::Ok {
+    ///     println!("Server is up");
+    /// }
+    /// ```
+    pub fn ping(&self) -> PingStatus {
+        // PQping never authenticates, so the credential provider is not asked
+        let config = session::balanced_config(&self.config);
+        let Ok(conninfo) = CString::new(config.to_conninfo()) else {
+            return PingStatus::NoAttempt;
+        };
+        match unsafe { PQping(conninfo.as_ptr()) } {
+            PQPING_OK => PingStatus::Ok,
+            PQPING_REJECT => PingStatus::Rejecting,
+            PQPING_NO_RESPONSE => PingStatus::NoResponse,
+            PQPING_NO_ATTEMPT => PingStatus::NoAttempt,
         }
+    }
 
-        let status = unsafe { PQresultStatus(result) };
+    /// Blocks until the server accepts connections or `timeout` elapses.
+    ///
+    /// The server is pinged repeatedly, waiting `backoff` after the first failed
+    /// attempt and doubling the wait after each further one. Returns an error if
+    /// the server is still not ready when the timeout is reached, or immediately
+    /// if the connection parameters are invalid.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use pgwire_lite::PgwireLite;
+    ///
+    /// let client = PgwireLite::new("localhost", 5444, false, "default")
+    ///     .expect("Failed to create client");
+    ///
+    /// client
+    ///     .wait_until_ready(Duration::from_secs(30), Duration::from_millis(100))
+    ///     .expect("Server did not start");
+    /// ```
+    pub fn wait_until_ready(
+        &self,
+        timeout: Duration,
+        backoff: Duration,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let deadline = Instant::now() + timeout;
+        let mut delay = backoff;
+        loop {
+            let status = self.ping();
+            debug!("Ping {}: {}", self.config.describe_hosts(), status);
+            match status {
+                PingStatus::Ok => return Ok(()),
+                PingStatus::NoAttempt => {
+                    return Err("Invalid connection parameters, server was not contacted".into())
+                }
+                PingStatus::Rejecting | PingStatus::NoResponse => {}
+            }
 
-        if status != PGRES_TUPLES_OK && status != PGRES_COMMAND_OK {
-            // Try to get a detailed error message
-            let error_msg_ptr = unsafe {
-                PQresultVerboseErrorMessage(
-                    result,
-                    self.verbosity.into(),
-                    PGContextVisibility::PQSHOW_CONTEXT_ALWAYS,
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(format!(
+                    "Server at {} not ready after {:?} ({})",
+                    self.config.describe_hosts(),
+                    timeout,
+                    status
                 )
-            };
-
-            let error_msg = if !error_msg_ptr.is_null() {
-                // Convert the C string to a Rust string
-                let msg = unsafe { CStr::from_ptr(error_msg_ptr).to_string_lossy().into_owned() };
-                // Free the C string allocated by PQresultVerboseErrorMessage
-                unsafe { libpq_sys::PQfreemem(error_msg_ptr as *mut _) };
-                msg
-            } else {
-                // Fallback to the standard connection error message if verbose message is not available
-                conn.error_message().unwrap_or("Unknown error").to_string()
-            };
+                .into());
+            }
+            thread::sleep(delay.min(deadline - now));
+            delay = delay.saturating_mul(2);
+        }
+    }
 
-            clear_pg_result(result);
+    /// Returns information about the server: its version, the backend process ID,
+    /// the protocol version and the ParameterStatus values it reported.
+    ///
+    /// In persistent mode this describes the client's open connection (opening it
+    /// if needed); in per-query mode a connection is opened just to read it.
+    /// See [`ServerInfo`].
+    pub fn server_info(&self) -> Result<ServerInfo, Box<dyn std::error::Error>> {
+        self.with_session(|session| Ok(session.server_info()))
+    }
 
-            // Clear any pending results
-            Self::consume_pending_results(&conn);
+    /// Returns the negotiated TLS session details.
+    ///
+    /// In persistent mode this describes the client's open connection (opening it
+    /// if needed); in per-query mode a connection is opened just to inspect the
+    /// session. Returns `Ok(None)` if the connection does not use TLS.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pgwire_lite::{ConnectionConfig, PgwireLite, SslMode, TlsConfig};
+    ///
+    /// let config = ConnectionConfig::new("127.0.0.1", 5444)
+    ///     .tls(TlsConfig::new(SslMode::VerifyCa).root_cert("server_cert.pem"));
+    /// let client = PgwireLite::from_config(config).expect("Failed to create client");
+    ///
+    /// if let Some(tls) = client.tls_info().expect("Connection failed") {
+    ///     println!("{:?} using {:?}", tls.protocol, tls.cipher);
+    /// }
+    /// ```
+    pub fn tls_info(&self) -> Result<Option<TlsInfo>, Box<dyn std::error::Error>> {
+        self.with_session(|session| Ok(session.tls_info().cloned()))
+    }
 
-            // return Err(format!("{}", error_msg.trim_end()).into());
-            return Err(error_msg.trim_end().to_string().into());
+    /// Returns whether the client holds an open persistent connection.
+    ///
+    /// Always `false` for clients in per-query mode.
+    pub fn is_connected(&self) -> bool {
+        match self.lock_session() {
+            Ok(session) => session.as_ref().is_some_and(|s| s.is_healthy()),
+            Err(_) => self.held_by_current_thread(),
         }
+    }
 
-        // Get column information
-        debug!("Getting column count");
-        let col_count = unsafe { PQnfields(result) };
-
-        // Create a vector to store column names
-        debug!("Getting column names");
-        let mut column_names = Vec::with_capacity(col_count as usize);
-        for col_index in 0..col_count {
-            let col_name_ptr = unsafe { PQfname(result, col_index) };
-            if !col_name_ptr.is_null() {
-                let col_name =
-                    unsafe { CStr::from_ptr(col_name_ptr).to_string_lossy().into_owned() };
-                column_names.push(col_name);
-            } else {
-                column_names.push(String::from("(unknown)"));
+    /// Closes the persistent connection, if one is open.
+    ///
+    /// The next query opens a new connection. Session state such as `SET`
+    /// values and temporary tables is lost. Does nothing while a row iterator or
+    /// copy on the calling thread holds the connection.
+    pub fn close(&self) {
+        if let Ok(mut session) = self.lock_session() {
+            if session.take().is_some() {
+                debug!("Closed persistent connection");
             }
         }
+    }
 
-        // Initialize row_count here
-        debug!("Getting row count");
-        let row_count = if status == PGRES_TUPLES_OK {
-            unsafe { PQntuples(result) }
-        } else {
-            0
-        };
+    // Runs `f` on a connection: a fresh one in per-query mode, or the persistent
+    // one (reconnecting if it is missing or broken) in persistent mode
+    fn with_session<T>(
+        &self,
+        f: impl FnOnce(&Session) -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        if !self.config.get_persistent() {
+            debug!("Opening a connection for this query");
+            let session = Session::connect(&self.config)?;
+            return f(&session);
+        }
 
-        // Create the rows vector
-        let mut rows = Vec::new();
-
-        // Get row data if available
-        if status == PGRES_TUPLES_OK {
-            debug!("Processing rows");
-
-            // Process each row
-            for row_index in 0..row_count {
-                let mut row_data = HashMap::new();
-
-                // Process each column in the row
-                for col_index in 0..col_count {
-                    let value_ptr = unsafe { PQgetvalue(result, row_index, col_index) };
-                    let value = if !value_ptr.is_null() {
-                        let string_value =
-                            unsafe { CStr::from_ptr(value_ptr).to_string_lossy().into_owned() };
-                        Value::String(string_value)
-                    } else {
-                        Value::Null
-                    };
+        let mut guard = self.persistent_session()?;
+        let session = guard.as_ref().ok_or("No persistent connection")?;
+        let result = f(session);
 
-                    // Insert value into the row map using the column name as key
-                    row_data.insert(column_names[col_index as usize].clone(), value);
+        // Drop a connection that broke during the call so the next call reconnects;
+        // the failed statement itself is not re-run
+        if result.is_err() && !session.is_healthy() {
+            debug!("Persistent connection lost during query, discarding it");
+            *guard = None;
+        }
+
+        result
+    }
+
+    // Locks the persistent connection, failing instead of waiting if a row iterator
+    // or copy on this thread holds it
+    fn lock_session(&self) -> Result<MutexGuard<'_, Option<Session>>, Box<dyn std::error::Error>> {
+        match self.session.try_lock() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::WouldBlock) => {
+                if self.held_by_current_thread() {
+                    return Err("Session busy: a row iterator or copy on this thread still holds the persistent connection".into());
                 }
+                self.session
+                    .lock()
+                    .map_err(|_| "Persistent connection lock poisoned".into())
+            }
+            Err(TryLockError::Poisoned(_)) => Err("Persistent connection lock poisoned".into()),
+        }
+    }
+
+    // Whether a row iterator or copy on this thread holds the persistent connection
+    fn held_by_current_thread(&self) -> bool {
+        let owner = self
+            .stream_owner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *owner == Some(thread::current().id())
+    }
+
+    // Locks the persistent connection, opening it or replacing a broken one first
+    fn persistent_session(
+        &self,
+    ) -> Result<MutexGuard<'_, Option<Session>>, Box<dyn std::error::Error>> {
+        let mut guard = self.lock_session()?;
 
-                rows.push(row_data);
+        let healthy = guard.as_ref().is_some_and(|s| s.is_healthy());
+        if !healthy {
+            if guard.take().is_some() {
+                debug!("Persistent connection is broken, reconnecting");
+            } else {
+                debug!("Opening persistent connection");
             }
+            *guard = Some(Session::connect(&self.config)?);
         }
-        debug!("Rows processed: {}", rows.len());
+        Ok(guard)
+    }
+}
+
+impl FromStr for PgwireLite {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_dsn(s)
+    }
+}
 
-        clear_pg_result(result);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Check for any remaining results and clear them
-        Self::consume_pending_results(&conn);
+    fn persistent_client() -> PgwireLite {
+        PgwireLite::from_config(ConnectionConfig::new("localhost", 5444).persistent(true))
+            .expect("valid configuration")
+    }
 
-        // Get the notices that were collected during the query
-        debug!("Collecting notices");
-        let notices = if let Ok(mut lock) = self.notices.lock() {
-            lock.drain(..).collect()
-        } else {
-            Vec::new()
-        };
-        let notice_count = notices.len();
+    #[test]
+    fn persistent_connection_opens_on_first_use() {
+        let client = persistent_client();
+        assert!(client.session.lock().unwrap().is_none());
+        assert!(!client.held_by_current_thread());
+    }
 
-        let elapsed_time_ms = start_time.elapsed().as_millis() as u64;
+    #[test]
+    fn calls_from_the_streaming_thread_fail_as_session_busy() {
+        let client = persistent_client();
+        let stream = SessionRef::persistent(client.session.lock().unwrap(), &client.stream_owner);
+        assert!(client.held_by_current_thread());
 
-        drop(conn);
+        let err = client.query("SELECT 1").unwrap_err();
+        assert!(err.to_string().starts_with("Session busy"), "{}", err);
 
-        Ok(QueryResult {
-            rows,
-            column_names,
-            notices,
-            row_count,
-            col_count,
-            notice_count,
-            status,
-            elapsed_time_ms,
-        })
+        drop(stream);
+        assert!(!client.held_by_current_thread());
+        assert!(client.lock_session().is_ok());
+    }
+
+    #[test]
+    fn other_threads_wait_for_the_stream() {
+        let client = persistent_client();
+        let stream = SessionRef::persistent(client.session.lock().unwrap(), &client.stream_owner);
+
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| client.lock_session().is_ok());
+            thread::sleep(Duration::from_millis(20));
+            assert!(!waiter.is_finished());
+            drop(stream);
+            assert!(waiter.join().unwrap());
+        });
     }
 }
@@ -0,0 +1,572 @@
+// src/copy.rs
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cancel::{CancelState, QueryCancelled};
+use crate::params::ToSql;
+use crate::rows::SessionRef;
+use crate::value::Value;
+
+// Size of the reads from a reader passed to `CopyIn::write_from`
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The format of the rows written with [`CopyIn::write_row`] or read with
+/// [`CopyOutIter`].
+///
+/// It must match the `FORMAT` option of the `COPY` statement, which must also use
+/// the default delimiter, `NULL` string and quote character of that format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CopyFormat {
+    /// Tab separated values with backslash escapes and `\N` for `NULL`.
+    #[default]
+    Text,
+    /// Comma separated values, quoted where needed, with an unquoted empty value
+    /// for `NULL`.
+    Csv,
+}
+
+/// A writer for the data of a `COPY ... FROM STDIN` statement, returned by
+/// [`PgwireLite::copy_in`](crate::PgwireLite::copy_in).
+///
+/// Data can be written as raw bytes through [`std::io::Write`], copied from any
+/// [`std::io::Read`] with [`CopyIn::write_from`], or built from rows of
+/// [`Value`]s with [`CopyIn::write_row`] and [`CopyIn::write_rows`]. Call
+/// [`CopyIn::finish`] to complete the copy; the server usually reports bad data
+/// only then.
+///
+/// While the writer is alive, a persistent client's connection is reserved for the
+/// copy: other threads wait for it, and the thread writing the data gets a
+/// "session busy" error if it queries the client before the copy ends. Dropping the
+/// writer without calling `finish` aborts the copy, so none of the data is loaded.
+pub struct CopyIn<'a> {
+    session: SessionRef<'a>,
+    cancel: Arc<CancelState>,
+    cancel_id: Option<u64>,
+    timeout: Option<Duration>,
+    format: CopyFormat,
+    finished: bool,
+}
+
+impl<'a> CopyIn<'a> {
+    // Starts the `COPY FROM STDIN` statement `query` on `session`
+    pub(crate) fn start(
+        mut session: SessionRef<'a>,
+        query: &str,
+        timeout: Option<Duration>,
+        cancel: Arc<CancelState>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Err(e) = cancel.run(&session, |s| s.start_copy_in(query, timeout)) {
+            session.discard_if_unhealthy();
+            return Err(e);
+        }
+        let cancel_id = cancel.begin(&session);
+        Ok(CopyIn {
+            session,
+            cancel,
+            cancel_id,
+            timeout,
+            format: CopyFormat::default(),
+            finished: false,
+        })
+    }
+
+    /// Sets the format of the rows written with [`CopyIn::write_row`]; the default
+    /// is [`CopyFormat::Text`].
+    pub fn format(mut self, format: CopyFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Returns the format of the rows written with [`CopyIn::write_row`].
+    pub fn get_format(&self) -> CopyFormat {
+        self.format
+    }
+
+    /// Writes one row, encoding its values in the writer's [`CopyFormat`].
+    pub fn write_row(&mut self, row: &[Value]) -> Result<(), Box<dyn std::error::Error>> {
+        let line = encode_row(self.format, row);
+        self.session.put_copy_data(line.as_bytes())
+    }
+
+    /// Writes every row of `rows`, as for [`CopyIn::write_row`].
+    pub fn write_rows<I>(&mut self, rows: I) -> Result<(), Box<dyn std::error::Error>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[Value]>,
+    {
+        for row in rows {
+            self.write_row(row.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Copies everything `reader` produces as raw data and returns the number of
+    /// bytes written.
+    pub fn write_from<R: Read>(
+        &mut self,
+        mut reader: R,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut buffer = vec![0; READ_BUFFER_SIZE];
+        let mut written = 0;
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(written),
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.session.put_copy_data(&buffer[..read])?;
+            written += read as u64;
+        }
+    }
+
+    /// Completes the copy and returns the number of rows loaded.
+    ///
+    /// The client's query timeout applies to the server loading the remaining data.
+    pub fn finish(mut self) -> Result<u64, Box<dyn std::error::Error>> {
+        self.finished = true;
+        let result = self.session.end_copy_in(self.timeout);
+        let cancelled = self.cancel.end(self.cancel_id.take());
+        match result {
+            Err(e) if cancelled => Err(Box::new(QueryCancelled {
+                message: e.to_string(),
+            })),
+            result => result,
+        }
+    }
+}
+
+impl Write for CopyIn<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.session
+            .put_copy_data(buf)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // libpq sends buffered data as the buffer fills and when the copy ends
+        Ok(())
+    }
+}
+
+impl Drop for CopyIn<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.cancel.end(self.cancel_id.take());
+            self.session.abort_copy_in("COPY abandoned by the client");
+        }
+        self.session.discard_if_unhealthy();
+    }
+}
+
+impl fmt::Debug for CopyIn<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CopyIn")
+            .field("format", &self.format)
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+/// An iterator over the rows of a `COPY ... TO STDOUT` statement, returned by
+/// [`PgwireLite::copy_out_iter`](crate::PgwireLite::copy_out_iter).
+///
+/// Each row is parsed from the writer's [`CopyFormat`] into one [`Value`] per
+/// column: [`Value::Null`] for `NULL` and [`Value::String`] otherwise, since COPY
+/// output carries no type information. A `HEADER` line is returned as a row.
+/// Binary output cannot be parsed; use
+/// [`PgwireLite::copy_out`](crate::PgwireLite::copy_out) for it.
+///
+/// Until every row is read or the iterator is dropped, a persistent client cannot
+/// run anything else: other threads wait, and the reading thread is refused with a
+/// "session busy" error. Dropping the iterator early cancels the copy, and the
+/// connection stays usable.
+pub struct CopyOutIter<'a> {
+    session: SessionRef<'a>,
+    cancel: Arc<CancelState>,
+    cancel_id: Option<u64>,
+    timeout: Option<Duration>,
+    format: CopyFormat,
+    buffer: Vec<u8>,
+    row_count: Option<u64>,
+    finished: bool,
+}
+
+impl<'a> CopyOutIter<'a> {
+    // Starts the `COPY TO STDOUT` statement `query` on `session`
+    pub(crate) fn start(
+        mut session: SessionRef<'a>,
+        query: &str,
+        timeout: Option<Duration>,
+        cancel: Arc<CancelState>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let binary = match cancel.run(&session, |s| s.start_copy_out(query, timeout)) {
+            Ok(binary) => binary,
+            Err(e) => {
+                session.discard_if_unhealthy();
+                return Err(e);
+            }
+        };
+        if binary {
+            session.abandon_copy_out();
+            return Err("Binary COPY output cannot be parsed into rows; use copy_out()".into());
+        }
+        let cancel_id = cancel.begin(&session);
+        Ok(CopyOutIter {
+            session,
+            cancel,
+            cancel_id,
+            timeout,
+            format: CopyFormat::default(),
+            buffer: Vec::new(),
+            row_count: None,
+            finished: false,
+        })
+    }
+
+    /// Sets the format the rows are parsed from; the default is [`CopyFormat::Text`].
+    pub fn format(mut self, format: CopyFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Returns the format the rows are parsed from.
+    pub fn get_format(&self) -> CopyFormat {
+        self.format
+    }
+
+    /// Returns the number of rows the server reported copying, once the last row
+    /// has been read.
+    pub fn row_count(&self) -> Option<u64> {
+        self.row_count
+    }
+
+    // Marks the copy as complete, reporting an error as a cancellation if a token
+    // cancelled it
+    fn finish(
+        &mut self,
+        error: Option<Box<dyn std::error::Error>>,
+    ) -> Option<Box<dyn std::error::Error>> {
+        self.finished = true;
+        let cancelled = self.cancel.end(self.cancel_id.take());
+        error.map(|e| -> Box<dyn std::error::Error> {
+            if cancelled {
+                Box::new(QueryCancelled {
+                    message: e.to_string(),
+                })
+            } else {
+                e
+            }
+        })
+    }
+}
+
+impl Iterator for CopyOutIter<'_> {
+    type Item = Result<Vec<Value>, Box<dyn std::error::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.session.read_copy_data(&mut self.buffer, self.timeout) {
+            Ok(true) => {
+                let line = self.buffer.strip_suffix(b"\n").unwrap_or(&self.buffer);
+                Some(Ok(match self.format {
+                    CopyFormat::Text => parse_text_row(line),
+                    CopyFormat::Csv => parse_csv_row(line),
+                }))
+            }
+            Ok(false) => match self.session.end_copy_out(self.timeout) {
+                Ok(rows) => {
+                    self.row_count = Some(rows);
+                    self.finish(None);
+                    None
+                }
+                Err(e) => self.finish(Some(e)).map(Err),
+            },
+            Err(e) => self.finish(Some(e)).map(Err),
+        }
+    }
+}
+
+impl Drop for CopyOutIter<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.cancel.end(self.cancel_id.take());
+            self.session.abandon_copy_out();
+        }
+        self.session.discard_if_unhealthy();
+    }
+}
+
+impl fmt::Debug for CopyOutIter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CopyOutIter")
+            .field("format", &self.format)
+            .field("row_count", &self.row_count)
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+// Encodes one row in `format`, including the line terminator
+fn encode_row(format: CopyFormat, row: &[Value]) -> String {
+    let mut line = String::new();
+    for (index, value) in row.iter().enumerate() {
+        match format {
+            CopyFormat::Text => {
+                if index > 0 {
+                    line.push('\t');
+                }
+                push_text_value(&mut line, value);
+            }
+            CopyFormat::Csv => {
+                if index > 0 {
+                    line.push(',');
+                }
+                push_csv_value(&mut line, value);
+            }
+        }
+    }
+    line.push('\n');
+    line
+}
+
+// Appends a value in COPY text format
+fn push_text_value(line: &mut String, value: &Value) {
+    let Some(text) = value.to_sql_text() else {
+        line.push_str("\\N");
+        return;
+    };
+    for c in text.chars() {
+        match c {
+            '\\' => line.push_str("\\\\"),
+            '\t' => line.push_str("\\t"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            c => line.push(c),
+        }
+    }
+}
+
+// Appends a value in COPY CSV format; an empty string is quoted to tell it from NULL
+fn push_csv_value(line: &mut String, value: &Value) {
+    let Some(text) = value.to_sql_text() else {
+        return;
+    };
+    let needs_quotes = text.is_empty() || text.contains([',', '"', '\n', '\r', '\\']);
+    if needs_quotes {
+        line.push('"');
+        line.push_str(&text.replace('"', "\"\""));
+        line.push('"');
+    } else {
+        line.push_str(&text);
+    }
+}
+
+// Parses a row in COPY text format, without its line ending
+fn parse_text_row(line: &[u8]) -> Vec<Value> {
+    line.split(|&byte| byte == b'\t')
+        .map(|field| {
+            if field == b"\\N" {
+                return Value::Null;
+            }
+            let mut text = Vec::with_capacity(field.len());
+            let mut bytes = field.iter();
+            while let Some(&byte) = bytes.next() {
+                if byte != b'\\' {
+                    text.push(byte);
+                    continue;
+                }
+                match bytes.next() {
+                    Some(b'b') => text.push(0x08),
+                    Some(b'f') => text.push(0x0c),
+                    Some(b'n') => text.push(b'\n'),
+                    Some(b'r') => text.push(b'\r'),
+                    Some(b't') => text.push(b'\t'),
+                    Some(b'v') => text.push(0x0b),
+                    Some(&other) => text.push(other),
+                    None => text.push(byte),
+                }
+            }
+            Value::String(String::from_utf8_lossy(&text).into_owned())
+        })
+        .collect()
+}
+
+// Parses a row in COPY CSV format, without its line ending; an unquoted empty
+// value is NULL
+fn parse_csv_row(line: &[u8]) -> Vec<Value> {
+    let mut values = Vec::new();
+    let mut field = Vec::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut bytes = line.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'"' if in_quotes => {
+                if bytes.peek() == Some(&&b'"') {
+                    bytes.next();
+                    field.push(b'"');
+                } else {
+                    in_quotes = false;
+                }
+            }
+            b'"' => {
+                in_quotes = true;
+                quoted = true;
+            }
+            b',' if !in_quotes => {
+                values.push(csv_value(&field, quoted));
+                field.clear();
+                quoted = false;
+            }
+            byte => field.push(byte),
+        }
+    }
+    values.push(csv_value(&field, quoted));
+    values
+}
+
+fn csv_value(field: &[u8], quoted: bool) -> Value {
+    if field.is_empty() && !quoted {
+        Value::Null
+    } else {
+        Value::String(String::from_utf8_lossy(field).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn text_values_escape_special_characters() {
+        assert_eq!(encode_row(CopyFormat::Text, &[Value::Null]), "\\N\n");
+        assert_eq!(encode_row(CopyFormat::Text, &[text("")]), "\n");
+        assert_eq!(encode_row(CopyFormat::Text, &[text("\\N")]), "\\\\N\n");
+        assert_eq!(encode_row(CopyFormat::Text, &[text("a\tb")]), "a\\tb\n");
+        assert_eq!(encode_row(CopyFormat::Text, &[text("a\\b")]), "a\\\\b\n");
+        assert_eq!(
+            encode_row(CopyFormat::Text, &[text("line 1\nline 2\r\n")]),
+            "line 1\\nline 2\\r\\n\n"
+        );
+        assert_eq!(
+            encode_row(CopyFormat::Text, &[text("say \"hi\", ok")]),
+            "say \"hi\", ok\n"
+        );
+        assert_eq!(
+            encode_row(
+                CopyFormat::Text,
+                &[Value::Integer(1), Value::Null, Value::Bool(true)]
+            ),
+            "1\t\\N\tt\n"
+        );
+    }
+
+    #[test]
+    fn csv_values_quote_when_needed() {
+        assert_eq!(encode_row(CopyFormat::Csv, &[Value::Null]), "\n");
+        assert_eq!(encode_row(CopyFormat::Csv, &[text("")]), "\"\"\n");
+        assert_eq!(encode_row(CopyFormat::Csv, &[text("plain")]), "plain\n");
+        assert_eq!(encode_row(CopyFormat::Csv, &[text("a,b")]), "\"a,b\"\n");
+        assert_eq!(
+            encode_row(CopyFormat::Csv, &[text("say \"hi\"")]),
+            "\"say \"\"hi\"\"\"\n"
+        );
+        assert_eq!(encode_row(CopyFormat::Csv, &[text("a\\b")]), "\"a\\b\"\n");
+        assert_eq!(
+            encode_row(CopyFormat::Csv, &[text("line 1\nline 2")]),
+            "\"line 1\nline 2\"\n"
+        );
+        assert_eq!(encode_row(CopyFormat::Csv, &[text("a\rb")]), "\"a\rb\"\n");
+        assert_eq!(encode_row(CopyFormat::Csv, &[text("a\tb")]), "a\tb\n");
+        assert_eq!(
+            encode_row(CopyFormat::Csv, &[Value::Integer(1), Value::Null, text("")]),
+            "1,,\"\"\n"
+        );
+    }
+
+    // Parsed values as optional strings, since Value has no equality
+    fn fields(values: Vec<Value>) -> Vec<Option<String>> {
+        values
+            .into_iter()
+            .map(|value| match value {
+                Value::Null => None,
+                Value::String(s) => Some(s),
+                other => panic!("unexpected value {:?}", other),
+            })
+            .collect()
+    }
+
+    fn assert_fields(values: Vec<Value>, expected: &[Option<&str>]) {
+        let fields = fields(values);
+        let fields: Vec<Option<&str>> = fields.iter().map(Option::as_deref).collect();
+        assert_eq!(fields, expected);
+    }
+
+    #[test]
+    fn parses_text_rows() {
+        assert_fields(
+            parse_text_row(b"1\t\\N\tplain"),
+            &[Some("1"), None, Some("plain")],
+        );
+        assert_fields(parse_text_row(b""), &[Some("")]);
+        assert_fields(parse_text_row(b"\t"), &[Some(""), Some("")]);
+        assert_fields(
+            parse_text_row(b"a\\tb\ta\\nb\\r\ta\\\\b"),
+            &[Some("a\tb"), Some("a\nb\r"), Some("a\\b")],
+        );
+        assert_fields(parse_text_row(b"\\b\\f\\v\\x"), &[Some("\u{8}\u{c}\u{b}x")]);
+        // An escaped backslash followed by N is the text \N, not NULL
+        assert_fields(parse_text_row(b"\\\\N"), &[Some("\\N")]);
+    }
+
+    #[test]
+    fn parses_csv_rows() {
+        assert_fields(
+            parse_csv_row(b"1,,\"\",plain"),
+            &[Some("1"), None, Some(""), Some("plain")],
+        );
+        assert_fields(parse_csv_row(b""), &[None]);
+        assert_fields(
+            parse_csv_row(b"\"a,b\",\"say \"\"hi\"\"\""),
+            &[Some("a,b"), Some("say \"hi\"")],
+        );
+        assert_fields(
+            parse_csv_row(b"\"line 1\nline 2\r\n\",x"),
+            &[Some("line 1\nline 2\r\n"), Some("x")],
+        );
+        assert_fields(parse_csv_row(b"a\\b,\"\"\"\""), &[Some("a\\b"), Some("\"")]);
+    }
+
+    #[test]
+    fn parsing_reverses_formatting() {
+        let values = vec![
+            text("tab\there"),
+            Value::Null,
+            text(""),
+            text("quote \" comma , backslash \\ newline \n"),
+        ];
+        let expected = fields(values.clone());
+        // Parsing takes the line without its terminator, as CopyOutIter passes it
+        let text_line = encode_row(CopyFormat::Text, &values);
+        let csv_line = encode_row(CopyFormat::Csv, &values);
+        assert_eq!(
+            fields(parse_text_row(text_line.trim_end_matches('\n').as_bytes())),
+            expected
+        );
+        assert_eq!(
+            fields(parse_csv_row(csv_line.trim_end_matches('\n').as_bytes())),
+            expected
+        );
+    }
+}
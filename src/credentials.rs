@@ -0,0 +1,240 @@
+// src/credentials.rs
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use log::debug;
+
+/// Credentials returned by a [`CredentialProvider`].
+#[derive(Clone)]
+pub struct Credentials {
+    /// User name to connect as. When `None`, the user set on the
+    /// [`ConnectionConfig`](crate::ConnectionConfig) is used.
+    pub user: Option<String>,
+
+    /// Password or token sent to the server.
+    pub password: String,
+
+    /// When the password stops being valid, or `None` if it does not expire.
+    ///
+    /// Cached credentials are reused until this instant, so providers should report
+    /// an expiry a little before the token actually expires to allow for clock skew
+    /// and the time taken to connect.
+    pub expires_at: Option<Instant>,
+}
+
+impl Credentials {
+    /// Creates credentials with the given password that never expire.
+    pub fn new(password: &str) -> Self {
+        Credentials {
+            user: None,
+            password: password.to_string(),
+            expires_at: None,
+        }
+    }
+
+    /// Sets the user name to connect as.
+    pub fn user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    /// Sets when the password stops being valid.
+    pub fn expires_at(mut self, expires_at: Instant) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| Instant::now() >= expires_at)
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("user", &self.user)
+            .field("password", &"********")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// A source of credentials that is consulted whenever a new connection is opened.
+///
+/// Use a provider when the password is a short-lived token, such as a cloud IAM
+/// database authentication token, instead of a static value. Credentials are cached
+/// and the provider is only called again once they have expired, or after a
+/// connection attempt with them has failed.
+///
+/// Closures returning `Result<Credentials, Box<dyn std::error::Error>>` implement
+/// this trait.
+///
+/// # Example
+///
+/// ```
+/// use std::time::{Duration, Instant};
+/// use pgwire_lite::{ConnectionConfig, Credentials};
+///
+/// fn fetch_token() -> String {
+///     String::from("token-from-identity-service")
+/// }
+///
+/// let config = ConnectionConfig::new("db.example.com", 5432)
+///     .user("app")
+///     .credential_provider(|| {
+///         Ok(Credentials::new(&fetch_token())
+///             .expires_at(Instant::now() + Duration::from_secs(14 * 60)))
+///     });
+/// ```
+pub trait CredentialProvider: Send + Sync {
+    /// Returns credentials for a new connection.
+    fn credentials(&self) -> Result<Credentials, Box<dyn std::error::Error>>;
+}
+
+impl<F> CredentialProvider for F
+where
+    F: Fn() -> Result<Credentials, Box<dyn std::error::Error>> + Send + Sync,
+{
+    fn credentials(&self) -> Result<Credentials, Box<dyn std::error::Error>> {
+        self()
+    }
+}
+
+/// A credential provider together with the credentials it last returned.
+///
+/// Shared between clones of a configuration, so a client and its pool reuse the
+/// same cached token.
+#[derive(Clone)]
+pub(crate) struct CredentialCache {
+    provider: Arc<dyn CredentialProvider>,
+    cached: Arc<Mutex<Option<Credentials>>>,
+}
+
+impl CredentialCache {
+    pub(crate) fn new(provider: impl CredentialProvider + 'static) -> Self {
+        CredentialCache {
+            provider: Arc::new(provider),
+            cached: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the cached credentials, asking the provider for new ones if they have expired.
+    pub(crate) fn get(&self) -> Result<Credentials, Box<dyn std::error::Error>> {
+        let mut cached = self
+            .cached
+            .lock()
+            .map_err(|_| "Credential cache lock poisoned")?;
+        if let Some(credentials) = cached.as_ref().filter(|c| !c.is_expired()) {
+            return Ok(credentials.clone());
+        }
+
+        debug!("Requesting credentials from the credential provider");
+        let credentials = self.provider.credentials()?;
+        *cached = Some(credentials.clone());
+        Ok(credentials)
+    }
+
+    /// Discards the cached credentials so the next connection asks the provider again.
+    pub(crate) fn invalidate(&self) {
+        if let Ok(mut cached) = self.cached.lock() {
+            *cached = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    // A cache whose provider hands out "token-1", "token-2", ... each valid for `lifetime`
+    fn counting_cache(lifetime: Duration) -> (CredentialCache, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let cache = CredentialCache::new(move || {
+            let call = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Credentials::new(&format!("token-{}", call)).expires_at(Instant::now() + lifetime))
+        });
+        (cache, calls)
+    }
+
+    #[test]
+    fn credentials_are_cached_until_they_expire() {
+        let (cache, calls) = counting_cache(Duration::from_secs(60));
+        assert_eq!(cache.get().unwrap().password, "token-1");
+        assert_eq!(cache.get().unwrap().password, "token-1");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn expired_credentials_are_fetched_again() {
+        let (cache, calls) = counting_cache(Duration::ZERO);
+        assert_eq!(cache.get().unwrap().password, "token-1");
+        assert_eq!(cache.get().unwrap().password, "token-2");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn credentials_without_expiry_are_kept() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let cache = CredentialCache::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Credentials::new("static").user("app"))
+        });
+        for _ in 0..3 {
+            let credentials = cache.get().unwrap();
+            assert_eq!(credentials.user.as_deref(), Some("app"));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn invalidate_forces_a_new_fetch() {
+        let (cache, calls) = counting_cache(Duration::from_secs(60));
+        cache.get().unwrap();
+        cache.invalidate();
+        assert_eq!(cache.get().unwrap().password, "token-2");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn clones_share_the_cached_credentials() {
+        let (cache, calls) = counting_cache(Duration::from_secs(60));
+        let clone = cache.clone();
+        cache.get().unwrap();
+        assert_eq!(clone.get().unwrap().password, "token-1");
+        clone.invalidate();
+        assert_eq!(cache.get().unwrap().password, "token-2");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn provider_errors_are_returned_and_not_cached() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let cache = CredentialCache::new(move || {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err("identity service unavailable".into())
+            } else {
+                Ok(Credentials::new("token"))
+            }
+        });
+        let err = cache.get().unwrap_err();
+        assert_eq!(err.to_string(), "identity service unavailable");
+        assert_eq!(cache.get().unwrap().password, "token");
+    }
+
+    #[test]
+    fn debug_output_masks_the_password() {
+        let credentials = Credentials::new("hunter2").user("app");
+        let debug = format!("{:?}", credentials);
+        assert!(!debug.contains("hunter2"));
+        assert!(debug.contains("********"));
+        assert!(debug.contains("app"));
+    }
+}
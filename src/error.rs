@@ -0,0 +1,148 @@
+// src/error.rs
+
+use std::fmt;
+
+use crate::connection::StatementResult;
+
+/// Where a [`DbError`] occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbErrorKind {
+    /// A connection to the server could not be opened. No statement was sent.
+    Connect,
+    /// The connection was lost while a statement was being executed, so it may or
+    /// may not have taken effect.
+    ConnectionLost,
+    /// The server reported an error executing the statement.
+    Server,
+}
+
+/// Error returned when opening a connection or executing a statement fails.
+///
+/// Returned (boxed) by [`PgwireLite::query`](crate::PgwireLite::query) and related
+/// methods; use `downcast_ref::<DbError>()` to inspect the SQLSTATE. The
+/// `Display` output is the message reported by libpq.
+///
+/// # Example
+///
+/// ```no_run
+/// use pgwire_lite::{DbError, PgwireLite};
+///
+/// let client = PgwireLite::new("localhost", 5444, false, "default")
+///     .expect("Failed to create client");
+///
+/// if let Err(e) = client.query("SELECT * FROM nonexistent_table") {
+///     if let Some(db_error) = e.downcast_ref::<DbError>() {
+///         println!("SQLSTATE {:?}: {}", db_error.sqlstate, db_error);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbError {
+    /// Where the error occurred.
+    pub kind: DbErrorKind,
+    /// The error message, formatted according to the client's verbosity.
+    pub message: String,
+    /// The five character SQLSTATE code, if the server reported one.
+    pub sqlstate: Option<String>,
+}
+
+impl DbError {
+    pub(crate) fn new(kind: DbErrorKind, message: &str) -> Self {
+        DbError {
+            kind,
+            message: message.to_string(),
+            sqlstate: None,
+        }
+    }
+
+    /// Returns the two character SQLSTATE class, e.g. `08` for connection exceptions.
+    pub fn sqlstate_class(&self) -> Option<&str> {
+        self.sqlstate.as_deref().and_then(|code| code.get(..2))
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for DbError {}
+
+/// Error returned by [`PgwireLite::query_multi`](crate::PgwireLite::query_multi)
+/// when one of the statements fails.
+#[derive(Debug)]
+pub struct StatementError {
+    /// Zero-based position of the failing statement.
+    pub index: usize,
+    /// The error reported for the failing statement.
+    pub error: DbError,
+    /// Results of the statements that completed before the failure.
+    pub results: Vec<StatementResult>,
+}
+
+impl fmt::Display for StatementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "statement {} failed: {}", self.index, self.error)
+    }
+}
+
+impl std::error::Error for StatementError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_error(sqlstate: Option<&str>) -> DbError {
+        DbError {
+            kind: DbErrorKind::Server,
+            message: "ERROR:  relation \"t\" does not exist".to_string(),
+            sqlstate: sqlstate.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn sqlstate_class_is_the_first_two_characters() {
+        assert_eq!(server_error(Some("08006")).sqlstate_class(), Some("08"));
+        assert_eq!(server_error(Some("42P01")).sqlstate_class(), Some("42"));
+    }
+
+    #[test]
+    fn sqlstate_class_needs_a_full_code() {
+        assert_eq!(server_error(None).sqlstate_class(), None);
+        assert_eq!(server_error(Some("4")).sqlstate_class(), None);
+        assert_eq!(
+            DbError::new(DbErrorKind::Connect, "could not connect").sqlstate_class(),
+            None
+        );
+    }
+
+    #[test]
+    fn display_is_the_message() {
+        assert_eq!(
+            server_error(Some("42P01")).to_string(),
+            "ERROR:  relation \"t\" does not exist"
+        );
+    }
+
+    #[test]
+    fn statement_error_names_the_failing_statement() {
+        let error = StatementError {
+            index: 2,
+            error: server_error(Some("42P01")),
+            results: Vec::new(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "statement 2 failed: ERROR:  relation \"t\" does not exist"
+        );
+
+        let source = std::error::Error::source(&error).expect("no source");
+        let db_error = source.downcast_ref::<DbError>().expect("not a DbError");
+        assert_eq!(db_error.sqlstate.as_deref(), Some("42P01"));
+    }
+}
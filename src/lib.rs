@@ -11,9 +11,30 @@
 //!
 //! - Built on the robust libpq C library
 //! - Simple API for query execution
+//! - Parameterized queries with [`ToSql`] values and explicit type OIDs
+//! - Prepared [`Statement`]s and a per-connection statement cache
+//! - Describing parameter types and result columns without executing via [`StatementDescription`]
+//! - Multi-statement queries with one result per statement
+//! - Batched statement submission in pipeline mode with [`Pipeline`]
+//! - Bulk loading with `COPY FROM STDIN` via [`CopyIn`]
+//! - Streaming exports with `COPY TO STDOUT` to a writer or a [`CopyOutIter`]
+//! - Streaming large results row by row with [`RowIter`]
 //! - Comprehensive error handling with configurable verbosity
-//! - Support for SSL/TLS connections
+//! - Support for SSL/TLS connections, including per-client mTLS via [`TlsConfig`]
+//! - Negotiated TLS session details for auditing via [`TlsInfo`]
+//! - Full connection configuration through [`ConnectionConfig`]
+//! - Connection URIs and keyword/value DSNs via [`PgwireLite::from_dsn`]
+//! - Optional persistent connections with automatic reconnect
+//! - Per-connection settings and initialization statements
+//! - Multi-host failover with [`TargetSessionAttrs`] and [`LoadBalanceHosts`]
+//! - Unix-domain and abstract socket connections via [`Host`]
+//! - Rotating passwords and tokens via a [`CredentialProvider`]
+//! - Per-query and client-wide execution time limits
+//! - Typed [`DbError`]s with SQLSTATE codes and a configurable [`RetryPolicy`]
+//! - Cancelling running queries from another thread with a [`CancelToken`]
+//! - A thread-safe connection [`Pool`]
 //! - Detailed query result information including notices
+//! - Server version, backend PID and reported parameters via [`ServerInfo`]
 //!
 //! ## Example
 //!
@@ -46,15 +67,79 @@
 //! }
 //! ```
 
+pub mod cancel;
+pub mod config;
 pub mod connection;
+pub mod copy;
+pub mod credentials;
+pub mod dsn;
+pub mod error;
 pub mod notices;
+pub mod params;
+pub mod pipeline;
+pub mod pool;
+pub mod retry;
+pub mod rows;
+pub mod server_info;
+mod session;
+pub mod statement;
+pub mod tls;
 pub mod value;
 
+// Re-export types from the cancel module
+pub use cancel::{CancelToken, QueryCancelled, QueryTimeout};
+
+// Re-export types from the config module
+pub use config::{
+    ChannelBinding, ConnectionConfig, Host, Keepalives, LoadBalanceHosts, TargetSessionAttrs,
+};
+
 // Re-export types from the connection module
-pub use connection::{PgwireLite, QueryResult};
+pub use connection::{
+    ColumnDescription, PgwireLite, PingStatus, QueryResult, StatementDescription, StatementResult,
+};
+
+// Re-export types from the copy module
+pub use copy::{CopyFormat, CopyIn, CopyOutIter};
+
+// Re-export types from the credentials module
+pub use credentials::{CredentialProvider, Credentials};
+
+// Re-export types from the dsn module
+pub use dsn::DsnError;
+
+// Re-export types from the error module
+pub use error::{DbError, DbErrorKind, StatementError};
+
+// Re-export types from the params module
+pub use params::{oid, Oid, ToSql, Typed};
+
+// Re-export types from the pipeline module
+pub use pipeline::{Pipeline, PipelineResult};
+
+// Re-export types from the pool module
+pub use pool::{Pool, PoolConfig, PoolStatus, PooledConnection};
+
+// Re-export types from the retry module
+pub use retry::RetryPolicy;
+
+// Re-export types from the rows module
+pub use rows::RowIter;
+
+// Re-export types from the server_info module
+pub use server_info::{ServerInfo, ServerVersion};
 
 // Re-export types from the notices module
 pub use notices::{Notice, Verbosity};
 
+// Re-export types from the statement module
+pub use statement::Statement;
+
+// Re-export types from the tls module
+pub use tls::{
+    CertificateInfo, CertificateUnavailable, FingerprintMismatch, SslMode, TlsConfig, TlsInfo,
+    TlsVersion,
+};
+
 // Re-export the Value type
 pub use value::Value;
@@ -0,0 +1,327 @@
+// src/params.rs
+
+use std::fmt::Write;
+
+use crate::value::Value;
+
+/// A PostgreSQL type OID, as stored in `pg_type.oid`.
+pub type Oid = u32;
+
+/// OIDs of the built-in types that [`ToSql`] values are sent as.
+///
+/// `UNSPECIFIED` lets the server infer the parameter type from the statement.
+pub mod oid {
+    use super::Oid;
+
+    /// Lets the server infer the type.
+    pub const UNSPECIFIED: Oid = 0;
+    /// `boolean`
+    pub const BOOL: Oid = 16;
+    /// `bytea`
+    pub const BYTEA: Oid = 17;
+    /// `bigint`
+    pub const INT8: Oid = 20;
+    /// `smallint`
+    pub const INT2: Oid = 21;
+    /// `integer`
+    pub const INT4: Oid = 23;
+    /// `text`
+    pub const TEXT: Oid = 25;
+    /// `json`
+    pub const JSON: Oid = 114;
+    /// `real`
+    pub const FLOAT4: Oid = 700;
+    /// `double precision`
+    pub const FLOAT8: Oid = 701;
+    /// `varchar`
+    pub const VARCHAR: Oid = 1043;
+    /// `date`
+    pub const DATE: Oid = 1082;
+    /// `timestamp`
+    pub const TIMESTAMP: Oid = 1114;
+    /// `timestamptz`
+    pub const TIMESTAMPTZ: Oid = 1184;
+    /// `numeric`
+    pub const NUMERIC: Oid = 1700;
+    /// `uuid`
+    pub const UUID: Oid = 2950;
+    /// `jsonb`
+    pub const JSONB: Oid = 3802;
+}
+
+/// A value that can be sent as a query parameter.
+///
+/// Parameters are sent separately from the SQL text, so they are never
+/// interpreted as SQL. Each value is sent in text format together with the OID
+/// of its PostgreSQL type; use [`Typed`] to send a value as a different type.
+///
+/// | Rust type | PostgreSQL type |
+/// |-----------|-----------------|
+/// | `bool` | `boolean` |
+/// | `i32` | `integer` |
+/// | `i64` | `bigint` |
+/// | `f64` | `double precision` |
+/// | `String`, `&str` | `text` |
+/// | `Vec<u8>`, `&[u8]` | `bytea` |
+/// | `Option<T>` | the type of `T`; `None` is sent as an untyped `NULL` |
+/// | [`Value`] | the type of the variant; `Value::Null` is an untyped `NULL` |
+pub trait ToSql {
+    /// Returns the OID of the type the value is sent as, or [`oid::UNSPECIFIED`]
+    /// to let the server infer it.
+    fn type_oid(&self) -> Oid;
+
+    /// Returns the text representation of the value, or `None` for `NULL`.
+    fn to_sql_text(&self) -> Option<String>;
+}
+
+impl<T: ToSql + ?Sized> ToSql for &T {
+    fn type_oid(&self) -> Oid {
+        (**self).type_oid()
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        (**self).to_sql_text()
+    }
+}
+
+impl ToSql for bool {
+    fn type_oid(&self) -> Oid {
+        oid::BOOL
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        Some(String::from(if *self { "t" } else { "f" }))
+    }
+}
+
+impl ToSql for i32 {
+    fn type_oid(&self) -> Oid {
+        oid::INT4
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+impl ToSql for i64 {
+    fn type_oid(&self) -> Oid {
+        oid::INT8
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+impl ToSql for f64 {
+    fn type_oid(&self) -> Oid {
+        oid::FLOAT8
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        // PostgreSQL spells the special values differently from Rust
+        let text = if self.is_nan() {
+            String::from("NaN")
+        } else if self.is_infinite() {
+            String::from(if *self > 0.0 { "Infinity" } else { "-Infinity" })
+        } else {
+            self.to_string()
+        };
+        Some(text)
+    }
+}
+
+impl ToSql for str {
+    fn type_oid(&self) -> Oid {
+        oid::TEXT
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+impl ToSql for String {
+    fn type_oid(&self) -> Oid {
+        oid::TEXT
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        Some(self.clone())
+    }
+}
+
+impl ToSql for [u8] {
+    fn type_oid(&self) -> Oid {
+        oid::BYTEA
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        // bytea hex format
+        let mut text = String::with_capacity(2 + self.len() * 2);
+        text.push_str("\\x");
+        for byte in self {
+            let _ = write!(text, "{:02x}", byte);
+        }
+        Some(text)
+    }
+}
+
+impl ToSql for Vec<u8> {
+    fn type_oid(&self) -> Oid {
+        oid::BYTEA
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        self.as_slice().to_sql_text()
+    }
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn type_oid(&self) -> Oid {
+        match self {
+            Some(value) => value.type_oid(),
+            None => oid::UNSPECIFIED,
+        }
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        self.as_ref().and_then(ToSql::to_sql_text)
+    }
+}
+
+impl ToSql for Value {
+    fn type_oid(&self) -> Oid {
+        match self {
+            Value::Null => oid::UNSPECIFIED,
+            Value::Bool(b) => b.type_oid(),
+            Value::Integer(i) => i.type_oid(),
+            Value::Float(f) => f.type_oid(),
+            Value::String(s) => s.type_oid(),
+            Value::Bytes(b) => b.type_oid(),
+        }
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        match self {
+            Value::Null => None,
+            Value::Bool(b) => b.to_sql_text(),
+            Value::Integer(i) => i.to_sql_text(),
+            Value::Float(f) => f.to_sql_text(),
+            Value::String(s) => s.to_sql_text(),
+            Value::Bytes(b) => b.to_sql_text(),
+        }
+    }
+}
+
+/// A parameter sent with an explicitly chosen type OID.
+///
+/// Use it for types without a Rust counterpart, or to let the server infer the
+/// type with [`oid::UNSPECIFIED`], for example when a string is compared with a
+/// column of another type.
+///
+/// # Example
+///
+/// ```
+/// use pgwire_lite::{oid, ToSql, Typed};
+///
+/// let created = Typed::new(&"2024-01-31", oid::DATE);
+/// assert_eq!(created.type_oid(), oid::DATE);
+/// assert_eq!(created.to_sql_text().as_deref(), Some("2024-01-31"));
+/// ```
+pub struct Typed<'a> {
+    value: &'a dyn ToSql,
+    oid: Oid,
+}
+
+impl<'a> Typed<'a> {
+    /// Wraps `value` so that it is sent as the type `oid`.
+    pub fn new(value: &'a dyn ToSql, oid: Oid) -> Self {
+        Typed { value, oid }
+    }
+}
+
+impl ToSql for Typed<'_> {
+    fn type_oid(&self) -> Oid {
+        self.oid
+    }
+
+    fn to_sql_text(&self) -> Option<String> {
+        self.value.to_sql_text()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(value: &dyn ToSql) -> (Oid, Option<String>) {
+        (value.type_oid(), value.to_sql_text())
+    }
+
+    #[test]
+    fn booleans_are_sent_as_t_and_f() {
+        assert_eq!(sent(&true), (oid::BOOL, Some("t".to_string())));
+        assert_eq!(sent(&false), (oid::BOOL, Some("f".to_string())));
+    }
+
+    #[test]
+    fn integers_keep_their_width() {
+        assert_eq!(sent(&-42i32), (oid::INT4, Some("-42".to_string())));
+        assert_eq!(
+            sent(&i64::MAX),
+            (oid::INT8, Some("9223372036854775807".to_string()))
+        );
+    }
+
+    #[test]
+    fn floats_use_postgres_spellings_for_special_values() {
+        assert_eq!(sent(&1.5f64), (oid::FLOAT8, Some("1.5".to_string())));
+        assert_eq!(f64::NAN.to_sql_text().as_deref(), Some("NaN"));
+        assert_eq!(f64::INFINITY.to_sql_text().as_deref(), Some("Infinity"));
+        assert_eq!(
+            f64::NEG_INFINITY.to_sql_text().as_deref(),
+            Some("-Infinity")
+        );
+    }
+
+    #[test]
+    fn strings_are_sent_unchanged() {
+        assert_eq!(sent(&"it's"), (oid::TEXT, Some("it's".to_string())));
+        assert_eq!(
+            sent(&String::from("a\\b")),
+            (oid::TEXT, Some("a\\b".to_string()))
+        );
+    }
+
+    #[test]
+    fn bytes_are_sent_in_hex_format() {
+        let bytes: &[u8] = &[0x00, 0x7f, 0xff];
+        assert_eq!(sent(&bytes), (oid::BYTEA, Some("\\x007fff".to_string())));
+        assert_eq!(
+            sent(&Vec::<u8>::new()),
+            (oid::BYTEA, Some("\\x".to_string()))
+        );
+    }
+
+    #[test]
+    fn none_is_an_untyped_null() {
+        assert_eq!(sent(&None::<i32>), (oid::UNSPECIFIED, None));
+        assert_eq!(sent(&Some(7i32)), (oid::INT4, Some("7".to_string())));
+        assert_eq!(sent(&Value::Null), (oid::UNSPECIFIED, None));
+    }
+
+    #[test]
+    fn values_are_sent_as_their_variant() {
+        assert_eq!(sent(&Value::Bool(true)), (oid::BOOL, Some("t".to_string())));
+        assert_eq!(
+            sent(&Value::Bytes(vec![0xab])),
+            (oid::BYTEA, Some("\\xab".to_string()))
+        );
+        assert_eq!(
+            sent(&Typed::new(&"2024-01-31", oid::DATE)),
+            (oid::DATE, Some("2024-01-31".to_string()))
+        );
+    }
+}
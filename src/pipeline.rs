@@ -0,0 +1,192 @@
+// src/pipeline.rs
+
+use crate::connection::StatementResult;
+use crate::error::DbError;
+use crate::params::{Oid, ToSql};
+
+// A statement queued in a pipeline, with its parameters already converted to text
+pub(crate) struct PipelineStatement {
+    pub(crate) query: String,
+    pub(crate) types: Vec<Oid>,
+    pub(crate) values: Vec<Option<String>>,
+}
+
+/// A batch of statements sent to the server together in libpq pipeline mode.
+///
+/// All statements are sent without waiting for the results of earlier ones, so a
+/// batch costs about one network round trip instead of one per statement. Run it
+/// with [`PgwireLite::execute_pipeline`](crate::PgwireLite::execute_pipeline), which
+/// returns one [`PipelineResult`] per statement, in order.
+///
+/// By default every statement is isolated: it runs in its own implicit transaction
+/// and a failing statement does not affect the others. With
+/// [`Pipeline::isolate_statements`] set to `false` the whole batch runs as one
+/// implicit transaction, so the first failure rolls back the statements before it
+/// and the statements after it are reported as [`PipelineResult::Aborted`].
+///
+/// # Example
+///
+/// ```
+/// use pgwire_lite::Pipeline;
+///
+/// let pipeline = Pipeline::new()
+///     .query("SELECT count(*) FROM aws.ec2.instances WHERE region = 'us-east-1'")
+///     .query_params("SELECT count(*) FROM aws.ec2.instances WHERE region = $1", &[&"eu-west-1"]);
+///
+/// assert_eq!(pipeline.len(), 2);
+/// ```
+pub struct Pipeline {
+    pub(crate) statements: Vec<PipelineStatement>,
+    pub(crate) isolate_statements: bool,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            statements: Vec::new(),
+            isolate_statements: true,
+        }
+    }
+}
+
+impl Pipeline {
+    /// Creates an empty pipeline with isolated statements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a single SQL statement.
+    pub fn query(self, query: &str) -> Self {
+        self.query_params(query, &[])
+    }
+
+    /// Queues a single SQL statement with parameters, as for
+    /// [`PgwireLite::query_params`](crate::PgwireLite::query_params).
+    pub fn query_params(mut self, query: &str, params: &[&dyn ToSql]) -> Self {
+        self.statements.push(PipelineStatement {
+            query: query.to_string(),
+            types: params.iter().map(|param| param.type_oid()).collect(),
+            values: params.iter().map(|param| param.to_sql_text()).collect(),
+        });
+        self
+    }
+
+    /// Sets whether each statement runs on its own, so that a failure does not
+    /// abort the others (the default), or the batch runs as one implicit transaction.
+    pub fn isolate_statements(mut self, isolate: bool) -> Self {
+        self.isolate_statements = isolate;
+        self
+    }
+
+    /// Returns the number of queued statements.
+    pub fn len(&self) -> usize {
+        self.statements.len()
+    }
+
+    /// Returns whether no statements are queued.
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    /// Returns whether statements are isolated from each other's failures.
+    pub fn get_isolate_statements(&self) -> bool {
+        self.isolate_statements
+    }
+}
+
+/// The outcome of one statement in a [`Pipeline`].
+#[derive(Debug)]
+pub enum PipelineResult {
+    /// The statement completed.
+    Completed(StatementResult),
+    /// The statement failed with the given error.
+    Failed(DbError),
+    /// The statement was skipped because an earlier statement in the same
+    /// transaction failed.
+    Aborted,
+}
+
+impl PipelineResult {
+    /// Returns the statement's result if it completed.
+    pub fn completed(&self) -> Option<&StatementResult> {
+        match self {
+            PipelineResult::Completed(result) => Some(result),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the statement completed.
+    pub fn is_completed(&self) -> bool {
+        matches!(self, PipelineResult::Completed(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DbErrorKind;
+    use crate::params::oid;
+
+    #[test]
+    fn statements_are_queued_in_order() {
+        let pipeline = Pipeline::new();
+        assert!(pipeline.is_empty());
+
+        let pipeline = pipeline.query("SELECT 1").query_params("SELECT $1", &[&7]);
+        assert_eq!(pipeline.len(), 2);
+        assert!(!pipeline.is_empty());
+        let queries: Vec<_> = pipeline.statements.iter().map(|s| &s.query).collect();
+        assert_eq!(queries, ["SELECT 1", "SELECT $1"]);
+        assert!(pipeline.statements[0].types.is_empty());
+        assert!(pipeline.statements[0].values.is_empty());
+    }
+
+    #[test]
+    fn parameters_are_converted_when_queued() {
+        let missing: Option<i32> = None;
+        let pipeline =
+            Pipeline::new().query_params("SELECT $1, $2, $3", &[&42, &"eu-west-1", &missing]);
+        let statement = &pipeline.statements[0];
+        assert_eq!(statement.types, [oid::INT4, oid::TEXT, oid::UNSPECIFIED]);
+        assert_eq!(
+            statement.values,
+            [Some("42".to_string()), Some("eu-west-1".to_string()), None]
+        );
+    }
+
+    #[test]
+    fn statements_are_isolated_by_default() {
+        assert!(Pipeline::new().get_isolate_statements());
+        assert!(Pipeline::default().get_isolate_statements());
+        assert!(!Pipeline::new()
+            .isolate_statements(false)
+            .get_isolate_statements());
+    }
+
+    #[test]
+    fn only_completed_statements_have_a_result() {
+        let completed = PipelineResult::Completed(StatementResult {
+            rows: Vec::new(),
+            column_names: Vec::new(),
+            notices: Vec::new(),
+            row_count: 0,
+            col_count: 0,
+            status: libpq_sys::ExecStatusType::PGRES_COMMAND_OK,
+            command_tag: "CREATE TABLE".to_string(),
+            rows_affected: None,
+            elapsed_time_ms: 0,
+        });
+        assert!(completed.is_completed());
+        assert_eq!(
+            completed.completed().map(|r| r.command_tag.as_str()),
+            Some("CREATE TABLE")
+        );
+
+        let failed = PipelineResult::Failed(DbError::new(DbErrorKind::Server, "division by zero"));
+        assert!(!failed.is_completed());
+        assert!(failed.completed().is_none());
+
+        assert!(!PipelineResult::Aborted.is_completed());
+        assert!(PipelineResult::Aborted.completed().is_none());
+    }
+}
@@ -0,0 +1,1077 @@
+// src/pool.rs
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::cancel::{CancelState, CancelToken};
+use crate::config::{ConnectionConfig, Host};
+use crate::connection::{QueryResult, StatementDescription, StatementResult};
+use crate::copy::{CopyIn, CopyOutIter};
+use crate::params::ToSql;
+use crate::pipeline::{Pipeline, PipelineResult};
+use crate::rows::{RowIter, SessionRef};
+use crate::server_info::ServerInfo;
+use crate::session::Session;
+use crate::statement::{check_statement_name, Statement};
+use crate::tls::TlsInfo;
+
+/// Sizing and lifecycle settings for a [`Pool`].
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use pgwire_lite::PoolConfig;
+///
+/// let pool_config = PoolConfig::new()
+///     .min_size(2)
+///     .max_size(16)
+///     .checkout_timeout(Duration::from_secs(5))
+///     .idle_timeout(Some(Duration::from_secs(300)))
+///     .max_lifetime(Some(Duration::from_secs(3600)))
+///     .test_on_checkout(true);
+///
+/// assert_eq!(pool_config.get_max_size(), 16);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    min_size: usize,
+    max_size: usize,
+    checkout_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    test_on_checkout: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            min_size: 0,
+            max_size: 10,
+            checkout_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(600)),
+            max_lifetime: Some(Duration::from_secs(1800)),
+            test_on_checkout: false,
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Creates pool settings with the defaults: up to 10 connections, none opened
+    /// up front, a 30 second checkout timeout, a 10 minute idle timeout and a
+    /// 30 minute maximum lifetime.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of connections opened when the pool is created and kept
+    /// open regardless of the idle timeout. Connections closed for exceeding
+    /// their maximum lifetime are replaced to keep at least this many open.
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets the maximum number of connections, idle and checked out combined.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets how long [`Pool::get`] waits for a connection when the pool is exhausted.
+    pub fn checkout_timeout(mut self, checkout_timeout: Duration) -> Self {
+        self.checkout_timeout = checkout_timeout;
+        self
+    }
+
+    /// Sets how long a connection above `min_size` may sit idle before it is closed.
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Sets the maximum age of a connection, after which it is closed instead of reused.
+    pub fn max_lifetime(mut self, max_lifetime: Option<Duration>) -> Self {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+
+    /// Sets whether idle connections are pinged with an empty query before being
+    /// handed out.
+    pub fn test_on_checkout(mut self, test_on_checkout: bool) -> Self {
+        self.test_on_checkout = test_on_checkout;
+        self
+    }
+
+    /// Returns the minimum number of connections.
+    pub fn get_min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Returns the maximum number of connections.
+    pub fn get_max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns the checkout timeout.
+    pub fn get_checkout_timeout(&self) -> Duration {
+        self.checkout_timeout
+    }
+
+    /// Returns the idle timeout.
+    pub fn get_idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Returns the maximum connection lifetime.
+    pub fn get_max_lifetime(&self) -> Option<Duration> {
+        self.max_lifetime
+    }
+
+    /// Returns whether connections are tested on checkout.
+    pub fn get_test_on_checkout(&self) -> bool {
+        self.test_on_checkout
+    }
+}
+
+/// A snapshot of the pool's connection counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStatus {
+    /// Open connections, idle and checked out.
+    pub total: usize,
+
+    /// Connections waiting in the pool.
+    pub idle: usize,
+
+    /// Connections currently checked out.
+    pub in_use: usize,
+}
+
+// What the pool needs from the connections it manages
+trait Poolable {
+    // Whether the connection is still usable
+    fn is_healthy(&self) -> bool;
+
+    // Round trip to the server, for test_on_checkout
+    fn ping(&self) -> bool;
+
+    // Leave any open transaction so the connection can be reused
+    fn reset_transaction(&self) -> bool;
+}
+
+impl Poolable for Session {
+    fn is_healthy(&self) -> bool {
+        Session::is_healthy(self)
+    }
+
+    fn ping(&self) -> bool {
+        Session::ping(self)
+    }
+
+    fn reset_transaction(&self) -> bool {
+        Session::reset_transaction(self)
+    }
+}
+
+// Opens a new connection for the pool
+type Connector<C> = Box<dyn Fn() -> Result<C, Box<dyn std::error::Error>> + Send + Sync>;
+
+// A pooled connection together with its lifecycle timestamps
+struct Entry<C> {
+    connection: C,
+    created_at: Instant,
+    idle_since: Instant,
+}
+
+struct PoolState<C> {
+    idle: VecDeque<Entry<C>>,
+    total: usize,
+}
+
+struct PoolInner<C = Session> {
+    config: ConnectionConfig,
+    pool_config: PoolConfig,
+    connect: Connector<C>,
+    state: Mutex<PoolState<C>>,
+    available: Condvar,
+}
+
+/// A thread-safe pool of connections to a PostgreSQL-compatible server.
+///
+/// The pool keeps between `min_size` and `max_size` connections open and hands
+/// them out as [`PooledConnection`] guards, which return the connection to the
+/// pool when dropped. `Pool` is cheap to clone; clones share the same connections.
+///
+/// # Example
+///
+/// ```no_run
+/// use pgwire_lite::{ConnectionConfig, Pool, PoolConfig};
+///
+/// let pool = Pool::new(
+///     ConnectionConfig::new("localhost", 5444),
+///     PoolConfig::new().max_size(8),
+/// )
+/// .expect("Failed to create pool");
+///
+/// let handles: Vec<_> = (0..4)
+///     .map(|i| {
+///         let pool = pool.clone();
+///         std::thread::spawn(move || {
+///             let conn = pool.get().expect("No connection available");
+///             conn.query(&format!("SELECT {} as worker", i)).expect("Query failed")
+///         })
+///     })
+///     .collect();
+///
+/// for handle in handles {
+///     println!("{:?}", handle.join().unwrap().rows);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+impl Pool {
+    /// Creates a pool and opens `min_size` connections.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The connection settings used for every pooled connection
+    /// * `pool_config` - The pool sizing and lifecycle settings
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the new Pool or an error if the settings are invalid
+    /// or one of the initial connections could not be opened
+    pub fn new(
+        config: ConnectionConfig,
+        pool_config: PoolConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        config.validate()?;
+        let connect_config = config.clone();
+        let inner = PoolInner::new(
+            config,
+            pool_config,
+            Box::new(move || Session::connect(&connect_config)),
+        )?;
+        Ok(Pool {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Checks out a connection, waiting up to the checkout timeout if all
+    /// `max_size` connections are in use.
+    ///
+    /// Connections are first opened until the pool holds `min_size` again, and
+    /// idle connections past their idle timeout or maximum lifetime are closed.
+    /// With `test_on_checkout` enabled a connection that fails its ping is replaced.
+    pub fn get(&self) -> Result<PooledConnection, Box<dyn std::error::Error>> {
+        let entry = self.inner.checkout()?;
+        Ok(PooledConnection::new(&self.inner, entry))
+    }
+
+    /// Returns the current connection counts.
+    pub fn status(&self) -> PoolStatus {
+        self.inner.status()
+    }
+
+    /// Returns the connection settings used for pooled connections.
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.inner.config
+    }
+
+    /// Returns the pool settings.
+    pub fn pool_config(&self) -> &PoolConfig {
+        &self.inner.pool_config
+    }
+}
+
+impl<C: Poolable> PoolInner<C> {
+    // Checks the sizing settings and opens min_size connections
+    fn new(
+        config: ConnectionConfig,
+        pool_config: PoolConfig,
+        connect: Connector<C>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if pool_config.max_size == 0 {
+            return Err("Pool max_size must be at least 1".into());
+        }
+        if pool_config.min_size > pool_config.max_size {
+            return Err(format!(
+                "Pool min_size ({}) is larger than max_size ({})",
+                pool_config.min_size, pool_config.max_size
+            )
+            .into());
+        }
+        // A connection with no lifetime would be replaced as soon as it was opened
+        if pool_config.max_lifetime == Some(Duration::ZERO) {
+            return Err("Pool max_lifetime must be longer than zero".into());
+        }
+
+        let mut idle = VecDeque::with_capacity(pool_config.max_size);
+        for _ in 0..pool_config.min_size {
+            idle.push_back(Entry::new(connect()?));
+        }
+        debug!("Pool created with {} connections", idle.len());
+
+        let total = idle.len();
+        Ok(PoolInner {
+            config,
+            pool_config,
+            connect,
+            state: Mutex::new(PoolState { idle, total }),
+            available: Condvar::new(),
+        })
+    }
+
+    // Hands out an idle connection or opens a new one, waiting up to the checkout
+    // timeout while all max_size connections are in use
+    fn checkout(&self) -> Result<Entry<C>, Box<dyn std::error::Error>> {
+        let deadline = Instant::now() + self.pool_config.checkout_timeout;
+        let mut state = self.lock_state();
+
+        // Replace connections closed since the last checkout
+        if state.total < self.pool_config.min_size {
+            drop(state);
+            self.replenish();
+            state = self.lock_state();
+        }
+
+        loop {
+            let expired = self.evict_expired(&mut state);
+            if !expired.is_empty() {
+                // Close the connections and open replacements without holding the lock
+                drop(state);
+                drop(expired);
+                self.replenish();
+                state = self.lock_state();
+                continue;
+            }
+
+            if let Some(entry) = state.idle.pop_back() {
+                if !self.pool_config.test_on_checkout {
+                    return Ok(entry);
+                }
+
+                // Ping without holding the lock so other checkouts are not blocked
+                drop(state);
+                if entry.connection.ping() {
+                    return Ok(entry);
+                }
+                debug!("Pooled connection failed its checkout ping, discarding it");
+                drop(entry);
+                self.release_slot();
+                state = self.lock_state();
+                continue;
+            }
+
+            if state.total < self.pool_config.max_size {
+                state.total += 1;
+                drop(state);
+
+                debug!("Opening a new pooled connection");
+                return match (self.connect)() {
+                    Ok(connection) => Ok(Entry::new(connection)),
+                    Err(e) => {
+                        self.release_slot();
+                        Err(e)
+                    }
+                };
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(format!(
+                    "Timed out after {:?} waiting for a pooled connection",
+                    self.pool_config.checkout_timeout
+                )
+                .into());
+            }
+            state = self
+                .available
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    // Takes back a checked out connection, closing it instead if it is broken,
+    // past its maximum lifetime, or stuck in a transaction
+    fn checkin(&self, mut entry: Entry<C>) {
+        let expired = self
+            .pool_config
+            .max_lifetime
+            .is_some_and(|lifetime| entry.created_at.elapsed() >= lifetime);
+        if expired || !entry.connection.is_healthy() || !entry.connection.reset_transaction() {
+            debug!("Closing pooled connection instead of returning it");
+            // The next checkout opens a replacement if the pool fell below min_size
+            drop(entry);
+            self.release_slot();
+            return;
+        }
+
+        entry.idle_since = Instant::now();
+        self.lock_state().idle.push_back(entry);
+        self.available.notify_one();
+    }
+
+    fn status(&self) -> PoolStatus {
+        let state = self.lock_state();
+        PoolStatus {
+            total: state.total,
+            idle: state.idle.len(),
+            in_use: state.total - state.idle.len(),
+        }
+    }
+
+    // Remove idle connections that exceeded their lifetime, or their idle timeout
+    // while the pool holds more than min_size connections; the caller closes them
+    // after releasing the lock
+    fn evict_expired(&self, state: &mut PoolState<C>) -> Vec<Entry<C>> {
+        let pool_config = &self.pool_config;
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        let mut index = 0;
+        while index < state.idle.len() {
+            let entry = &state.idle[index];
+            let too_old = pool_config
+                .max_lifetime
+                .is_some_and(|lifetime| now.duration_since(entry.created_at) >= lifetime);
+            let too_idle = state.total > pool_config.min_size
+                && pool_config
+                    .idle_timeout
+                    .is_some_and(|timeout| now.duration_since(entry.idle_since) >= timeout);
+            if too_old || too_idle {
+                debug!(
+                    "Evicting pooled connection ({})",
+                    if too_old {
+                        "max lifetime"
+                    } else {
+                        "idle timeout"
+                    }
+                );
+                expired.extend(state.idle.remove(index));
+                state.total -= 1;
+            } else {
+                index += 1;
+            }
+        }
+        expired
+    }
+
+    // The pool state only holds counts and idle connections, which stay consistent
+    // even if a thread panicked while holding the lock
+    fn lock_state(&self) -> MutexGuard<'_, PoolState<C>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Give up a connection slot and wake a waiting checkout
+    fn release_slot(&self) {
+        self.lock_state().total -= 1;
+        self.available.notify_one();
+    }
+
+    // Open connections until the pool holds at least min_size again
+    fn replenish(&self) {
+        loop {
+            {
+                let mut state = self.lock_state();
+                if state.total >= self.pool_config.min_size {
+                    return;
+                }
+                state.total += 1;
+            }
+
+            debug!("Opening a pooled connection to keep min_size open");
+            match (self.connect)() {
+                Ok(connection) => {
+                    self.lock_state().idle.push_back(Entry::new(connection));
+                    self.available.notify_one();
+                }
+                Err(e) => {
+                    debug!("Could not replace pooled connection: {}", e);
+                    self.release_slot();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<C> Entry<C> {
+    fn new(connection: C) -> Self {
+        let now = Instant::now();
+        Entry {
+            connection,
+            created_at: now,
+            idle_since: now,
+        }
+    }
+}
+
+/// A connection checked out from a [`Pool`].
+///
+/// Provides the same query API as [`PgwireLite`](crate::PgwireLite), including
+/// retries under the [`RetryPolicy`](crate::RetryPolicy) of the pool's connection
+/// settings. The connection is returned to the pool when the guard is dropped;
+/// connections that are broken, past their maximum lifetime, or left inside a
+/// transaction that cannot be rolled back are closed instead.
+///
+/// While a [`RowIter`], [`CopyIn`] or [`CopyOutIter`] from the guard is alive,
+/// other calls on it that talk to the server fail with a "session busy" error.
+pub struct PooledConnection {
+    pool: Arc<PoolInner>,
+    entry: Option<Entry<Session>>,
+    cancel: Arc<CancelState>,
+    streaming: AtomicBool,
+}
+
+impl PooledConnection {
+    fn new(pool: &Arc<PoolInner>, entry: Entry<Session>) -> Self {
+        PooledConnection {
+            pool: Arc::clone(pool),
+            entry: Some(entry),
+            cancel: Arc::new(CancelState::default()),
+            streaming: AtomicBool::new(false),
+        }
+    }
+
+    fn session(&self) -> &Session {
+        &self
+            .entry
+            .as_ref()
+            .expect("pooled connection already released")
+            .connection
+    }
+
+    // Returns the connection for a new command, failing if a row iterator or copy
+    // from this guard is still reading from or writing to it
+    fn available_session(&self) -> Result<&Session, Box<dyn std::error::Error>> {
+        if self.streaming.load(Ordering::Acquire) {
+            return Err(
+                "Session busy: a row iterator or copy still holds the pooled connection".into(),
+            );
+        }
+        Ok(self.session())
+    }
+
+    // Holds the connection for a row iterator or copy
+    fn stream_session(&self) -> Result<SessionRef<'_>, Box<dyn std::error::Error>> {
+        Ok(SessionRef::pooled(
+            self.available_session()?,
+            &self.streaming,
+        ))
+    }
+
+    /// Executes a SQL query on the pooled connection and returns the results.
+    ///
+    /// See [`PgwireLite::query`](crate::PgwireLite::query).
+    pub fn query(&self, query: &str) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        self.run_query_with_retries(query, self.pool.config.get_query_timeout(), false)
+    }
+
+    /// Executes a SQL query with a time limit on the pooled connection.
+    ///
+    /// See [`PgwireLite::query_with_timeout`](crate::PgwireLite::query_with_timeout).
+    /// A connection whose timed out query could not be cancelled is closed
+    /// instead of being returned to the pool.
+    pub fn query_with_timeout(
+        &self,
+        query: &str,
+        timeout: Duration,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        self.run_query_with_retries(query, Some(timeout), false)
+    }
+
+    /// Executes an idempotent SQL query on the pooled connection and returns the results.
+    ///
+    /// See [`PgwireLite::query_idempotent`](crate::PgwireLite::query_idempotent).
+    /// The guard keeps its connection, so a query whose connection was lost is
+    /// not retried; check out another connection instead.
+    pub fn query_idempotent(&self, query: &str) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        self.run_query_with_retries(query, self.pool.config.get_query_timeout(), true)
+    }
+
+    /// Executes a SQL query on the pooled connection and returns an iterator over
+    /// its rows as they arrive.
+    ///
+    /// See [`PgwireLite::query_iter`](crate::PgwireLite::query_iter).
+    pub fn query_iter(&self, query: &str) -> Result<RowIter<'_>, Box<dyn std::error::Error>> {
+        RowIter::start(
+            self.stream_session()?,
+            query,
+            self.pool.config.get_query_timeout(),
+            Arc::clone(&self.cancel),
+        )
+    }
+
+    /// Starts a `COPY ... FROM STDIN` statement on the pooled connection and returns
+    /// a writer for its data.
+    ///
+    /// See [`PgwireLite::copy_in`](crate::PgwireLite::copy_in).
+    pub fn copy_in(&self, query: &str) -> Result<CopyIn<'_>, Box<dyn std::error::Error>> {
+        CopyIn::start(
+            self.stream_session()?,
+            query,
+            self.pool.config.get_query_timeout(),
+            Arc::clone(&self.cancel),
+        )
+    }
+
+    /// Runs a `COPY ... TO STDOUT` statement on the pooled connection and writes
+    /// its output to `writer` as it arrives.
+    ///
+    /// See [`PgwireLite::copy_out`](crate::PgwireLite::copy_out).
+    pub fn copy_out<W: Write>(
+        &self,
+        query: &str,
+        writer: W,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let timeout = self.pool.config.get_query_timeout();
+        let writer = RefCell::new(writer);
+        self.run_with_retries(false, |s| {
+            s.copy_out(query, &mut *writer.borrow_mut(), timeout)
+        })
+        .map(|(rows, _)| rows)
+    }
+
+    /// Runs a `COPY ... TO STDOUT` statement on the pooled connection and returns
+    /// an iterator over its rows.
+    ///
+    /// See [`PgwireLite::copy_out_iter`](crate::PgwireLite::copy_out_iter).
+    pub fn copy_out_iter(
+        &self,
+        query: &str,
+    ) -> Result<CopyOutIter<'_>, Box<dyn std::error::Error>> {
+        CopyOutIter::start(
+            self.stream_session()?,
+            query,
+            self.pool.config.get_query_timeout(),
+            Arc::clone(&self.cancel),
+        )
+    }
+
+    /// Executes a SQL query with parameters on the pooled connection and returns the results.
+    ///
+    /// See [`PgwireLite::query_params`](crate::PgwireLite::query_params).
+    pub fn query_params(
+        &self,
+        query: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        let timeout = self.pool.config.get_query_timeout();
+        let cached = self.pool.config.get_statement_cache_size() > 0;
+        let (mut result, attempts) = self.run_with_retries(false, |s| {
+            if cached {
+                s.query_cached(query, params, timeout)
+            } else {
+                s.query_params(query, params, timeout)
+            }
+        })?;
+        result.attempts = attempts;
+        Ok(result)
+    }
+
+    /// Prepares a named statement on the pooled connection.
+    ///
+    /// See [`PgwireLite::prepare`](crate::PgwireLite::prepare). The returned
+    /// statement can be executed on any connection of the pool.
+    pub fn prepare(
+        &self,
+        name: &str,
+        query: &str,
+    ) -> Result<Statement, Box<dyn std::error::Error>> {
+        check_statement_name(name)?;
+        let statement = Statement::new(name, query);
+        self.available_session()?.prepare(&statement)?;
+        Ok(statement)
+    }
+
+    /// Executes a prepared statement on the pooled connection, preparing it first
+    /// if this connection does not have it yet.
+    ///
+    /// See [`PgwireLite::execute_prepared`](crate::PgwireLite::execute_prepared).
+    pub fn execute_prepared(
+        &self,
+        statement: &Statement,
+        params: &[&dyn ToSql],
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        let timeout = self.pool.config.get_query_timeout();
+        let (mut result, attempts) =
+            self.run_with_retries(false, |s| s.query_prepared(statement, params, timeout))?;
+        result.attempts = attempts;
+        Ok(result)
+    }
+
+    /// Describes a SQL statement on the pooled connection without executing it.
+    ///
+    /// See [`PgwireLite::describe`](crate::PgwireLite::describe).
+    pub fn describe(
+        &self,
+        query: &str,
+    ) -> Result<StatementDescription, Box<dyn std::error::Error>> {
+        self.available_session()?.describe(query)
+    }
+
+    /// Describes a prepared statement on the pooled connection.
+    ///
+    /// See [`PgwireLite::describe_prepared`](crate::PgwireLite::describe_prepared).
+    pub fn describe_prepared(
+        &self,
+        statement: &Statement,
+    ) -> Result<StatementDescription, Box<dyn std::error::Error>> {
+        self.available_session()?.describe_prepared(statement)
+    }
+
+    /// Describes an open cursor (portal) on the pooled connection.
+    ///
+    /// See [`PgwireLite::describe_portal`](crate::PgwireLite::describe_portal).
+    pub fn describe_portal(
+        &self,
+        portal: &str,
+    ) -> Result<StatementDescription, Box<dyn std::error::Error>> {
+        self.available_session()?.describe_portal(portal)
+    }
+
+    /// Executes several SQL statements on the pooled connection and returns one
+    /// result per statement.
+    ///
+    /// See [`PgwireLite::query_multi`](crate::PgwireLite::query_multi).
+    pub fn query_multi(
+        &self,
+        query: &str,
+    ) -> Result<Vec<StatementResult>, Box<dyn std::error::Error>> {
+        let timeout = self.pool.config.get_query_timeout();
+        self.run_with_retries(false, |s| s.query_multi(query, timeout))
+            .map(|(results, _)| results)
+    }
+
+    /// Sends all statements of a pipeline on the pooled connection at once and
+    /// returns one result per statement.
+    ///
+    /// See [`PgwireLite::execute_pipeline`](crate::PgwireLite::execute_pipeline).
+    pub fn execute_pipeline(
+        &self,
+        pipeline: &Pipeline,
+    ) -> Result<Vec<PipelineResult>, Box<dyn std::error::Error>> {
+        let timeout = self.pool.config.get_query_timeout();
+        self.run_with_retries(false, |s| s.execute_pipeline(pipeline, timeout))
+            .map(|(results, _)| results)
+    }
+
+    /// Returns a token that cancels queries running on this pooled connection from another thread.
+    ///
+    /// See [`PgwireLite::cancel_token`](crate::PgwireLite::cancel_token).
+    pub fn cancel_token(&self) -> CancelToken {
+        CancelToken::new(Arc::clone(&self.cancel))
+    }
+
+    /// Returns the server from the host list that the pooled connection is connected to.
+    pub fn server(&self) -> &Host {
+        self.session().server()
+    }
+
+    /// Returns information about the server of the pooled connection.
+    ///
+    /// See [`PgwireLite::server_info`](crate::PgwireLite::server_info).
+    pub fn server_info(&self) -> ServerInfo {
+        self.session().server_info()
+    }
+
+    /// Returns the TLS session details of the pooled connection, or `None` if it does not use TLS.
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.session().tls_info()
+    }
+
+    /// Returns whether the pooled connection is still usable.
+    pub fn is_connected(&self) -> bool {
+        self.session().is_healthy()
+    }
+
+    // Runs a query, retrying transient failures as allowed by the retry policy
+    fn run_query_with_retries(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+        idempotent: bool,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        let (mut result, attempts) =
+            self.run_with_retries(idempotent, |s| s.query(query, timeout))?;
+        result.attempts = attempts;
+        Ok(result)
+    }
+
+    // Runs a cancellable operation on the connection, retrying transient failures
+    // as allowed by the retry policy for as long as the connection stays usable;
+    // returns the result and the number of attempts
+    fn run_with_retries<T>(
+        &self,
+        idempotent: bool,
+        f: impl Fn(&Session) -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<(T, u32), Box<dyn std::error::Error>> {
+        let session = self.available_session()?;
+        let mut attempt = 1;
+        loop {
+            match self.cancel.run(session, &f) {
+                Ok(result) => return Ok((result, attempt)),
+                Err(e) => {
+                    let delay = self
+                        .pool
+                        .config
+                        .get_retry_policy()
+                        .filter(|_| session.is_healthy())
+                        .and_then(|policy| policy.retry_delay(attempt, e.as_ref(), idempotent));
+                    let Some(delay) = delay else {
+                        return Err(e);
+                    };
+                    debug!(
+                        "Attempt {} failed ({}), retrying in {:?}",
+                        attempt,
+                        e.to_string().trim_end(),
+                        delay
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.pool.checkin(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    struct FakeConnection {
+        id: usize,
+        healthy: bool,
+    }
+
+    impl Poolable for FakeConnection {
+        fn is_healthy(&self) -> bool {
+            self.healthy
+        }
+
+        fn ping(&self) -> bool {
+            self.healthy
+        }
+
+        fn reset_transaction(&self) -> bool {
+            true
+        }
+    }
+
+    // A pool of fake connections numbered in the order they were opened, with a
+    // counter of how many were opened
+    fn fake_pool(pool_config: PoolConfig) -> (PoolInner<FakeConnection>, Arc<AtomicUsize>) {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&opened);
+        let connect: Connector<FakeConnection> = Box::new(move || {
+            Ok(FakeConnection {
+                id: counter.fetch_add(1, Ordering::SeqCst) + 1,
+                healthy: true,
+            })
+        });
+        let pool = PoolInner::new(
+            ConnectionConfig::new("localhost", 5444),
+            pool_config,
+            connect,
+        )
+        .expect("valid pool settings");
+        (pool, opened)
+    }
+
+    fn short_timeout() -> PoolConfig {
+        PoolConfig::new().checkout_timeout(Duration::from_millis(50))
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let connect = || -> Connector<FakeConnection> { Box::new(|| Err("unused".into())) };
+        let config = ConnectionConfig::new("localhost", 5444);
+
+        let err = PoolInner::new(config.clone(), PoolConfig::new().max_size(0), connect())
+            .err()
+            .expect("max_size 0 is rejected");
+        assert!(err.to_string().contains("max_size"));
+
+        let err = PoolInner::new(
+            config.clone(),
+            PoolConfig::new().max_lifetime(Some(Duration::ZERO)),
+            connect(),
+        )
+        .err()
+        .expect("a zero max_lifetime is rejected");
+        assert!(err.to_string().contains("max_lifetime"));
+
+        let err = PoolInner::new(config, PoolConfig::new().min_size(3).max_size(2), connect())
+            .err()
+            .expect("min_size above max_size is rejected");
+        assert!(err.to_string().contains("larger than max_size"));
+    }
+
+    #[test]
+    fn opens_min_size_connections_up_front() {
+        let (pool, opened) = fake_pool(short_timeout().min_size(2));
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            pool.status(),
+            PoolStatus {
+                total: 2,
+                idle: 2,
+                in_use: 0
+            }
+        );
+    }
+
+    #[test]
+    fn checkout_times_out_at_max_size() {
+        let (pool, opened) = fake_pool(short_timeout().max_size(2));
+        let first = pool.checkout().unwrap();
+        let second = pool.checkout().unwrap();
+        assert_ne!(first.connection.id, second.connection.id);
+
+        let started = Instant::now();
+        let err = pool.checkout().err().expect("pool is exhausted");
+        assert!(err.to_string().contains("Timed out"));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.status().in_use, 2);
+    }
+
+    #[test]
+    fn checkin_returns_the_connection_for_reuse() {
+        let (pool, opened) = fake_pool(short_timeout().max_size(1));
+        let entry = pool.checkout().unwrap();
+        let id = entry.connection.id;
+        pool.checkin(entry);
+        assert_eq!(pool.status().idle, 1);
+
+        assert_eq!(pool.checkout().unwrap().connection.id, id);
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn waiting_checkout_is_woken_by_a_checkin() {
+        let (pool, _) = fake_pool(PoolConfig::new().max_size(1));
+        let entry = pool.checkout().unwrap();
+        let id = entry.connection.id;
+
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| pool.checkout().map(|entry| entry.connection.id).ok());
+            thread::sleep(Duration::from_millis(20));
+            pool.checkin(entry);
+            assert_eq!(waiter.join().unwrap(), Some(id));
+        });
+    }
+
+    #[test]
+    fn broken_connections_are_closed_on_checkin() {
+        let (pool, _) = fake_pool(short_timeout().max_size(1));
+        let mut entry = pool.checkout().unwrap();
+        entry.connection.healthy = false;
+        pool.checkin(entry);
+        assert_eq!(
+            pool.status(),
+            PoolStatus {
+                total: 0,
+                idle: 0,
+                in_use: 0
+            }
+        );
+
+        // The freed slot is available to the next checkout
+        assert_eq!(pool.checkout().unwrap().connection.id, 2);
+    }
+
+    #[test]
+    fn idle_connections_above_min_size_are_evicted() {
+        let (pool, _) = fake_pool(
+            short_timeout()
+                .min_size(1)
+                .idle_timeout(Some(Duration::ZERO)),
+        );
+        let first = pool.checkout().unwrap();
+        let second = pool.checkout().unwrap();
+        pool.checkin(first);
+        pool.checkin(second);
+        assert_eq!(pool.status().idle, 2);
+
+        // Eviction stops at min_size, leaving one connection to hand out
+        let entry = pool.checkout().unwrap();
+        assert_eq!(
+            pool.status(),
+            PoolStatus {
+                total: 1,
+                idle: 0,
+                in_use: 1
+            }
+        );
+        pool.checkin(entry);
+    }
+
+    #[test]
+    fn connections_past_their_lifetime_are_replaced() {
+        let lifetime = Duration::from_millis(30);
+        let (pool, opened) = fake_pool(short_timeout().min_size(1).max_lifetime(Some(lifetime)));
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+
+        // The idle connection has expired, so checkout replaces it
+        thread::sleep(lifetime);
+        let entry = pool.checkout().unwrap();
+        assert_eq!(entry.connection.id, 2);
+
+        // An expired connection is closed rather than returned
+        thread::sleep(lifetime);
+        pool.checkin(entry);
+        assert_eq!(pool.status().total, 0);
+
+        // The next checkout reopens min_size connections first
+        assert_eq!(pool.checkout().unwrap().connection.id, 3);
+        assert_eq!(pool.status().total, 1);
+    }
+
+    #[test]
+    fn failed_checkout_ping_discards_the_connection() {
+        let (pool, _) = fake_pool(short_timeout().max_size(1).test_on_checkout(true));
+        let mut entry = pool.checkout().unwrap();
+        // Put it back as if it broke while idle
+        entry.connection.healthy = false;
+        pool.lock_state().idle.push_back(entry);
+
+        assert_eq!(pool.checkout().unwrap().connection.id, 2);
+        assert_eq!(pool.status().total, 1);
+    }
+
+    #[test]
+    fn failed_connect_releases_its_slot() {
+        let connect: Connector<FakeConnection> = Box::new(|| Err("connection refused".into()));
+        let pool = PoolInner::new(
+            ConnectionConfig::new("localhost", 5444),
+            short_timeout().max_size(1),
+            connect,
+        )
+        .unwrap();
+
+        let err = pool.checkout().err().expect("connect fails");
+        assert_eq!(err.to_string(), "connection refused");
+        assert_eq!(pool.status().total, 0);
+    }
+}
@@ -0,0 +1,348 @@
+// src/retry.rs
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::{DbError, DbErrorKind};
+
+type RetryPredicate = Arc<dyn Fn(&DbError) -> bool + Send + Sync>;
+
+/// Controls how [`PgwireLite`](crate::PgwireLite) retries queries that fail with a
+/// transient error.
+///
+/// Failures to open a connection are retried for every query, since the statement
+/// was never sent; if the server reported a SQLSTATE while the connection was set
+/// up, for example from a failing init statement, it is retried only if that
+/// SQLSTATE is. Failures during execution, such as a serialization failure or a
+/// connection lost mid-query, are only retried for statements run with
+/// [`PgwireLite::query_idempotent`](crate::PgwireLite::query_idempotent), because
+/// the statement may already have taken effect.
+///
+/// Between attempts the client waits for an exponentially growing backoff, capped
+/// at the maximum backoff; with jitter enabled, each wait is a random duration
+/// between half and all of the backoff.
+///
+/// By default up to 3 attempts are made, starting with a 100ms backoff capped at
+/// 10s, and the retried errors are connection failures, lost connections and the
+/// SQLSTATEs of class `08` (connection exception), `40001` (serialization failure),
+/// `40P01` (deadlock detected) and `57P01` (admin shutdown).
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use pgwire_lite::{ConnectionConfig, RetryPolicy};
+///
+/// let policy = RetryPolicy::new()
+///     .max_attempts(5)
+///     .initial_backoff(Duration::from_millis(200))
+///     .max_backoff(Duration::from_secs(5))
+///     .retry_on_sqlstate("55P03"); // lock_not_available
+///
+/// let config = ConnectionConfig::new("localhost", 5444).retry_policy(policy);
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    retry_connection_errors: bool,
+    sqlstates: Vec<String>,
+    predicate: Option<RetryPredicate>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
+            retry_connection_errors: true,
+            sqlstates: ["08", "40001", "40P01", "57P01"]
+                .iter()
+                .map(|code| code.to_string())
+                .collect(),
+            predicate: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a retry policy with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the total number of attempts, including the first. `1` disables retries.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets the wait before the first retry.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the longest wait between attempts.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Sets whether waits are randomised to spread out retries from many clients.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets whether failures to connect and connections lost mid-query are retried.
+    pub fn retry_connection_errors(mut self, retry: bool) -> Self {
+        self.retry_connection_errors = retry;
+        self
+    }
+
+    /// Adds a SQLSTATE to retry: either a full five character code such as `40001`,
+    /// or a two character class such as `08`.
+    pub fn retry_on_sqlstate(mut self, sqlstate: &str) -> Self {
+        self.sqlstates.push(sqlstate.to_string());
+        self
+    }
+
+    /// Replaces the retried SQLSTATE codes and classes.
+    pub fn retry_on_sqlstates(mut self, sqlstates: &[&str]) -> Self {
+        self.sqlstates = sqlstates.iter().map(|code| code.to_string()).collect();
+        self
+    }
+
+    /// Replaces the error classification with a custom predicate.
+    ///
+    /// The predicate decides which errors are transient; the rule that only
+    /// idempotent statements are retried after they were sent still applies.
+    pub fn retry_if(
+        mut self,
+        predicate: impl Fn(&DbError) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Returns the total number of attempts.
+    pub fn get_max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns the wait before the first retry.
+    pub fn get_initial_backoff(&self) -> Duration {
+        self.initial_backoff
+    }
+
+    /// Returns the longest wait between attempts.
+    pub fn get_max_backoff(&self) -> Duration {
+        self.max_backoff
+    }
+
+    /// Returns whether an error is considered transient by this policy.
+    pub fn is_retryable(&self, error: &DbError) -> bool {
+        if let Some(predicate) = &self.predicate {
+            return predicate(error);
+        }
+        match error.kind {
+            // The server rejected the session setup, e.g. a failing init statement
+            DbErrorKind::Connect if error.sqlstate.is_some() => self.retries_sqlstate(error),
+            DbErrorKind::Connect | DbErrorKind::ConnectionLost => self.retry_connection_errors,
+            DbErrorKind::Server => self.retries_sqlstate(error),
+        }
+    }
+
+    // Whether the error's SQLSTATE matches a retried code or class
+    fn retries_sqlstate(&self, error: &DbError) -> bool {
+        error.sqlstate.as_deref().is_some_and(|code| {
+            self.sqlstates.iter().any(|retried| {
+                code == retried || (retried.len() == 2 && code.starts_with(retried.as_str()))
+            })
+        })
+    }
+
+    /// Returns how long to wait before retrying after `attempt` failed with `error`,
+    /// or `None` if the query should not be retried.
+    pub(crate) fn retry_delay(
+        &self,
+        attempt: u32,
+        error: &(dyn std::error::Error + 'static),
+        idempotent: bool,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let error = error.downcast_ref::<DbError>()?;
+        if error.kind != DbErrorKind::Connect && !idempotent {
+            return None;
+        }
+        if !self.is_retryable(error) {
+            return None;
+        }
+
+        let exponent = attempt.saturating_sub(1).min(31);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        if !self.jitter {
+            return Some(backoff);
+        }
+        let half = backoff / 2;
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(attempt);
+        let fraction = (hasher.finish() % 1_000) as u32;
+        Some(half + half * fraction / 1_000)
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("jitter", &self.jitter)
+            .field("retry_connection_errors", &self.retry_connection_errors)
+            .field("sqlstates", &self.sqlstates)
+            .field("predicate", &self.predicate.as_ref().map(|_| "custom"))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_error(sqlstate: &str) -> DbError {
+        DbError {
+            kind: DbErrorKind::Server,
+            message: "error".to_string(),
+            sqlstate: Some(sqlstate.to_string()),
+        }
+    }
+
+    fn connect_error() -> DbError {
+        DbError::new(DbErrorKind::Connect, "could not connect")
+    }
+
+    fn fixed_policy() -> RetryPolicy {
+        RetryPolicy::new()
+            .max_attempts(10)
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(1_000))
+            .jitter(false)
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_cap() {
+        let policy = fixed_policy();
+        let delays: Vec<_> = (1..=6)
+            .map(|attempt| policy.retry_delay(attempt, &connect_error(), false))
+            .collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1_000, 1_000]
+                .map(|ms| Some(Duration::from_millis(ms)))
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn jitter_stays_between_half_and_all_of_the_backoff() {
+        let policy = fixed_policy().jitter(true);
+        for attempt in 1..=6 {
+            let backoff = fixed_policy()
+                .retry_delay(attempt, &connect_error(), false)
+                .unwrap();
+            let delay = policy
+                .retry_delay(attempt, &connect_error(), false)
+                .unwrap();
+            assert!(delay >= backoff / 2 && delay <= backoff, "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn stops_after_max_attempts() {
+        let policy = fixed_policy().max_attempts(3);
+        assert!(policy.retry_delay(2, &connect_error(), false).is_some());
+        assert_eq!(policy.retry_delay(3, &connect_error(), false), None);
+    }
+
+    #[test]
+    fn only_connect_errors_are_retried_for_non_idempotent_statements() {
+        let policy = fixed_policy();
+        let lost = DbError::new(DbErrorKind::ConnectionLost, "server closed the connection");
+        assert!(policy.retry_delay(1, &connect_error(), false).is_some());
+        assert_eq!(policy.retry_delay(1, &lost, false), None);
+        assert_eq!(policy.retry_delay(1, &server_error("40001"), false), None);
+        assert!(policy.retry_delay(1, &lost, true).is_some());
+        assert!(policy
+            .retry_delay(1, &server_error("40001"), true)
+            .is_some());
+    }
+
+    #[test]
+    fn default_sqlstates_are_retried() {
+        let policy = fixed_policy();
+        for code in ["08006", "08P01", "40001", "40P01", "57P01"] {
+            assert!(
+                policy.retry_delay(1, &server_error(code), true).is_some(),
+                "{}",
+                code
+            );
+        }
+        for code in ["23505", "40002", "57014", "42P01"] {
+            assert_eq!(
+                policy.retry_delay(1, &server_error(code), true),
+                None,
+                "{}",
+                code
+            );
+        }
+        let no_sqlstate = DbError::new(DbErrorKind::Server, "error");
+        assert_eq!(policy.retry_delay(1, &no_sqlstate, true), None);
+    }
+
+    #[test]
+    fn connect_errors_with_a_sqlstate_are_classified_by_it() {
+        let policy = fixed_policy();
+        let init_failure = |sqlstate: &str| DbError {
+            kind: DbErrorKind::Connect,
+            message: "Initialization statement failed".to_string(),
+            sqlstate: Some(sqlstate.to_string()),
+        };
+        assert_eq!(policy.retry_delay(1, &init_failure("42601"), false), None);
+        assert_eq!(policy.retry_delay(1, &init_failure("42501"), false), None);
+        assert!(policy
+            .retry_delay(1, &init_failure("57P01"), false)
+            .is_some());
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let error = std::io::Error::other("broken pipe");
+        assert_eq!(fixed_policy().retry_delay(1, &error, true), None);
+    }
+
+    #[test]
+    fn custom_predicate_replaces_the_classification() {
+        let policy = fixed_policy().retry_if(|error| error.sqlstate.as_deref() == Some("55P03"));
+        assert!(policy
+            .retry_delay(1, &server_error("55P03"), true)
+            .is_some());
+        assert_eq!(policy.retry_delay(1, &server_error("40001"), true), None);
+        assert_eq!(policy.retry_delay(1, &connect_error(), false), None);
+        // Only idempotent statements are retried once sent, whatever the predicate says
+        assert_eq!(policy.retry_delay(1, &server_error("55P03"), false), None);
+    }
+}
@@ -0,0 +1,204 @@
+// src/rows.rs
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+use log::debug;
+
+use crate::cancel::{CancelState, QueryCancelled};
+use crate::session::Session;
+use crate::value::Value;
+
+// The connection a row iterator reads from, held for as long as the iterator lives
+pub(crate) enum SessionRef<'a> {
+    // A connection opened for this query alone
+    Owned(Box<Session>),
+    // The persistent connection of a client, with the client's record of which
+    // thread is streaming from it
+    Persistent(MutexGuard<'a, Option<Session>>, &'a Mutex<Option<ThreadId>>),
+    // A connection checked out from a pool, with the guard's flag that it is
+    // streaming
+    Pooled(&'a Session, &'a AtomicBool),
+}
+
+impl<'a> SessionRef<'a> {
+    // Holds a persistent connection for streaming, recording the current thread so
+    // that further calls on it fail instead of waiting for the lock forever
+    pub(crate) fn persistent(
+        guard: MutexGuard<'a, Option<Session>>,
+        owner: &'a Mutex<Option<ThreadId>>,
+    ) -> Self {
+        *owner.lock().unwrap_or_else(PoisonError::into_inner) = Some(thread::current().id());
+        SessionRef::Persistent(guard, owner)
+    }
+
+    // Holds a pooled connection for streaming, flagging it so that further calls
+    // on its guard fail until the stream is dropped
+    pub(crate) fn pooled(session: &'a Session, streaming: &'a AtomicBool) -> Self {
+        streaming.store(true, Ordering::Release);
+        SessionRef::Pooled(session, streaming)
+    }
+
+    // Lets a persistent client reconnect if its connection broke while in use
+    pub(crate) fn discard_if_unhealthy(&mut self) {
+        if let SessionRef::Persistent(guard, _) = self {
+            if guard.as_ref().is_some_and(|session| !session.is_healthy()) {
+                debug!("Persistent connection lost, discarding it");
+                **guard = None;
+            }
+        }
+    }
+}
+
+impl Drop for SessionRef<'_> {
+    fn drop(&mut self) {
+        match self {
+            // Cleared before the guard is released
+            SessionRef::Persistent(_, owner) => {
+                *owner.lock().unwrap_or_else(PoisonError::into_inner) = None;
+            }
+            SessionRef::Pooled(_, streaming) => streaming.store(false, Ordering::Release),
+            SessionRef::Owned(_) => {}
+        }
+    }
+}
+
+impl Deref for SessionRef<'_> {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        match self {
+            SessionRef::Owned(session) => session,
+            SessionRef::Persistent(guard, _) => guard
+                .as_ref()
+                .expect("persistent connection is open while rows are streamed"),
+            SessionRef::Pooled(session, _) => session,
+        }
+    }
+}
+
+/// An iterator over the rows of a query, returned by
+/// [`PgwireLite::query_iter`](crate::PgwireLite::query_iter).
+///
+/// Rows are read from the server as they arrive instead of being collected into
+/// a [`QueryResult`](crate::QueryResult) first, so memory use does not grow with
+/// the size of the result. Each row is a map of column names to values, as in
+/// [`QueryResult::rows`](crate::QueryResult::rows).
+///
+/// The iterator holds its connection until it is dropped. On a persistent client,
+/// queries from other threads block until then, while a query from the thread that
+/// owns the iterator fails with a "session busy" error instead of deadlocking.
+/// Other calls on the [`PooledConnection`](crate::PooledConnection) it came from
+/// fail with the same error.
+/// Dropping the iterator before the last row cancels the query, and the connection
+/// stays usable.
+pub struct RowIter<'a> {
+    session: SessionRef<'a>,
+    cancel: Arc<CancelState>,
+    cancel_id: Option<u64>,
+    timeout: Option<Duration>,
+    column_names: Option<Vec<String>>,
+    buffered: VecDeque<HashMap<String, Value>>,
+    finished: bool,
+}
+
+impl<'a> RowIter<'a> {
+    // Starts streaming `query` on `session`
+    pub(crate) fn start(
+        mut session: SessionRef<'a>,
+        query: &str,
+        timeout: Option<Duration>,
+        cancel: Arc<CancelState>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Err(e) = session.start_row_stream(query) {
+            session.discard_if_unhealthy();
+            return Err(e);
+        }
+        let cancel_id = cancel.begin(&session);
+        Ok(RowIter {
+            session,
+            cancel,
+            cancel_id,
+            timeout,
+            column_names: None,
+            buffered: VecDeque::new(),
+            finished: false,
+        })
+    }
+
+    /// Returns the names of the result columns, or `None` until the server has
+    /// described the result.
+    ///
+    /// The names arrive with the first row, or once the iterator has returned
+    /// `None` for a query without rows.
+    pub fn column_names(&self) -> Option<&[String]> {
+        self.column_names.as_deref()
+    }
+
+    // Marks the query as complete, reporting an error as a cancellation if a
+    // token cancelled it
+    fn finish(
+        &mut self,
+        error: Option<Box<dyn std::error::Error>>,
+    ) -> Option<Box<dyn std::error::Error>> {
+        self.finished = true;
+        let cancelled = self.cancel.end(self.cancel_id.take());
+        error.map(|e| -> Box<dyn std::error::Error> {
+            if cancelled {
+                Box::new(QueryCancelled {
+                    message: e.to_string(),
+                })
+            } else {
+                e
+            }
+        })
+    }
+}
+
+impl Iterator for RowIter<'_> {
+    type Item = Result<HashMap<String, Value>, Box<dyn std::error::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffered.is_empty() {
+            if self.finished {
+                return None;
+            }
+            match self.session.next_rows(self.timeout) {
+                Ok(Some((column_names, rows))) => {
+                    self.column_names.get_or_insert(column_names);
+                    self.buffered.extend(rows);
+                }
+                Ok(None) => {
+                    self.finish(None);
+                }
+                Err(e) => return self.finish(Some(e)).map(Err),
+            }
+        }
+        self.buffered.pop_front().map(Ok)
+    }
+}
+
+impl Drop for RowIter<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.cancel.end(self.cancel_id.take());
+            self.session.abandon_row_stream();
+        }
+        self.session.discard_if_unhealthy();
+    }
+}
+
+impl fmt::Debug for RowIter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RowIter")
+            .field("column_names", &self.column_names)
+            .field("buffered", &self.buffered.len())
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
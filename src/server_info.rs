@@ -0,0 +1,214 @@
+// src/server_info.rs
+
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::fmt;
+
+use libpq::Connection;
+use libpq_sys::{PQbackendPID, PQparameterStatus, PQprotocolVersion, PQserverVersion};
+
+// Parameters the server reports to the client through ParameterStatus messages
+const REPORTED_PARAMETERS: &[&str] = &[
+    "application_name",
+    "client_encoding",
+    "DateStyle",
+    "default_transaction_read_only",
+    "in_hot_standby",
+    "integer_datetimes",
+    "IntervalStyle",
+    "is_superuser",
+    "scram_iterations",
+    "search_path",
+    "server_encoding",
+    "server_version",
+    "session_authorization",
+    "standard_conforming_strings",
+    "TimeZone",
+];
+
+/// A server version number, as reported by `PQserverVersion`.
+///
+/// From PostgreSQL 10 on, versions have two parts (e.g. `15.4`) and `patch` is
+/// always `0`; earlier versions have three (e.g. `9.6.24`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ServerVersion {
+    /// Major version, e.g. `15` or `9`.
+    pub major: u32,
+    /// Minor version, e.g. `4` for 15.4 or `6` for 9.6.24.
+    pub minor: u32,
+    /// Patch level for versions before 10, e.g. `24` for 9.6.24.
+    pub patch: u32,
+}
+
+impl ServerVersion {
+    /// Parses the integer form returned by `PQserverVersion`, e.g. `150004`
+    /// or `90624`. Returns `None` for `0`, which libpq reports when the
+    /// connection is bad.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use pgwire_lite::ServerVersion;
+    ///
+    /// let version = ServerVersion::from_number(150004).unwrap();
+    /// assert_eq!((version.major, version.minor), (15, 4));
+    /// assert_eq!(ServerVersion::from_number(90624).unwrap().to_string(), "9.6.24");
+    /// assert!(version > ServerVersion::from_number(90624).unwrap());
+    /// ```
+    pub fn from_number(number: i32) -> Option<Self> {
+        if number <= 0 {
+            return None;
+        }
+        let number = number as u32;
+        if number >= 100000 {
+            Some(ServerVersion {
+                major: number / 10000,
+                minor: number % 10000,
+                patch: 0,
+            })
+        } else {
+            Some(ServerVersion {
+                major: number / 10000,
+                minor: (number / 100) % 100,
+                patch: number % 100,
+            })
+        }
+    }
+}
+
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.major >= 10 {
+            write!(f, "{}.{}", self.major, self.minor)
+        } else {
+            write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+        }
+    }
+}
+
+/// Information about the server a connection is attached to.
+///
+/// Parameter values are a snapshot taken when the information was requested;
+/// settings such as `TimeZone` change if the session runs `SET`.
+///
+/// # Example
+///
+/// ```no_run
+/// use pgwire_lite::PgwireLite;
+///
+/// let client = PgwireLite::new("localhost", 5444, false, "default")
+///     .expect("Failed to create client");
+/// let info = client.server_info().expect("Connection failed");
+///
+/// println!("Server {:?} (pid {})", info.server_version, info.backend_pid);
+/// println!("TimeZone: {:?}", info.parameter("TimeZone"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// Parsed server version, if the server reported one.
+    pub version: Option<ServerVersion>,
+    /// The `server_version` string reported by the server, e.g. `15.4 (Debian 15.4-1)`.
+    pub server_version: Option<String>,
+    /// Process ID of the backend serving the connection.
+    pub backend_pid: i32,
+    /// Frontend/backend protocol version, e.g. `3`.
+    pub protocol_version: i32,
+    /// Values of the ParameterStatus settings reported by the server, such as
+    /// `server_encoding`, `TimeZone`, `DateStyle`, `integer_datetimes` and
+    /// `standard_conforming_strings`.
+    pub parameters: BTreeMap<String, String>,
+}
+
+impl ServerInfo {
+    /// Returns the value of a ParameterStatus setting, if the server reported it.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(String::as_str)
+    }
+}
+
+// Reads a ParameterStatus value, which is NULL when the server did not report it
+fn parameter_status(conn: &Connection, name: &str) -> Option<String> {
+    let name = CString::new(name).ok()?;
+    unsafe {
+        let value = PQparameterStatus(conn.into(), name.as_ptr());
+        if value.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(value).to_string_lossy().into_owned())
+        }
+    }
+}
+
+/// Collects the server information of an open connection.
+pub(crate) fn server_info(conn: &Connection) -> ServerInfo {
+    let parameters = REPORTED_PARAMETERS
+        .iter()
+        .filter_map(|name| parameter_status(conn, name).map(|value| (name.to_string(), value)))
+        .collect::<BTreeMap<_, _>>();
+
+    ServerInfo {
+        version: ServerVersion::from_number(unsafe { PQserverVersion(conn.into()) }),
+        server_version: parameters.get("server_version").cloned(),
+        backend_pid: unsafe { PQbackendPID(conn.into()) },
+        protocol_version: unsafe { PQprotocolVersion(conn.into()) },
+        parameters,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u32, minor: u32, patch: u32) -> ServerVersion {
+        ServerVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    #[test]
+    fn bad_connections_have_no_version() {
+        assert_eq!(ServerVersion::from_number(0), None);
+        assert_eq!(ServerVersion::from_number(-1), None);
+    }
+
+    #[test]
+    fn versions_before_10_have_three_parts() {
+        assert_eq!(ServerVersion::from_number(90624), Some(version(9, 6, 24)));
+        assert_eq!(ServerVersion::from_number(80400), Some(version(8, 4, 0)));
+        assert_eq!(version(9, 6, 24).to_string(), "9.6.24");
+    }
+
+    #[test]
+    fn versions_from_10_have_two_parts() {
+        assert_eq!(ServerVersion::from_number(100000), Some(version(10, 0, 0)));
+        assert_eq!(ServerVersion::from_number(150004), Some(version(15, 4, 0)));
+        assert_eq!(version(10, 0, 0).to_string(), "10.0");
+        assert_eq!(version(15, 4, 0).to_string(), "15.4");
+    }
+
+    #[test]
+    fn versions_order_numerically() {
+        let versions = [90624, 100000, 100023, 150004, 160000]
+            .map(|number| ServerVersion::from_number(number).unwrap());
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn parameters_are_looked_up_by_exact_name() {
+        let info = ServerInfo {
+            version: ServerVersion::from_number(150004),
+            server_version: Some("15.4".to_string()),
+            backend_pid: 4242,
+            protocol_version: 3,
+            parameters: BTreeMap::from([
+                ("TimeZone".to_string(), "UTC".to_string()),
+                ("server_encoding".to_string(), "UTF8".to_string()),
+            ]),
+        };
+        assert_eq!(info.parameter("TimeZone"), Some("UTC"));
+        assert_eq!(info.parameter("timezone"), None);
+        assert_eq!(info.parameter("DateStyle"), None);
+    }
+}
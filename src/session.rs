@@ -0,0 +1,1910 @@
+// src/session.rs
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CStr, CString, NulError};
+use std::io::Write;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use libpq::Connection;
+use libpq_sys::ConnStatusType::{CONNECTION_OK, CONNECTION_SSL_STARTUP};
+use libpq_sys::ExecStatusType::{
+    PGRES_COMMAND_OK, PGRES_COPY_IN, PGRES_COPY_OUT, PGRES_EMPTY_QUERY, PGRES_PIPELINE_ABORTED,
+    PGRES_PIPELINE_SYNC, PGRES_SINGLE_TUPLE, PGRES_TUPLES_OK,
+};
+use libpq_sys::PGTransactionStatusType::{PQTRANS_IDLE, PQTRANS_INERROR, PQTRANS_INTRANS};
+use libpq_sys::{
+    PGContextVisibility, PQbinaryTuples, PQclear, PQcmdStatus, PQcmdTuples, PQconsumeInput,
+    PQdescribePortal, PQdescribePrepared, PQenterPipelineMode, PQexec, PQexitPipelineMode, PQflush,
+    PQfmod, PQfname, PQfsize, PQftable, PQftablecol, PQftype, PQgetCopyData, PQgetResult,
+    PQgetvalue, PQhost, PQisBusy, PQlibVersion, PQnfields, PQnparams, PQntuples, PQparamtype,
+    PQpipelineSync, PQport, PQprepare, PQputCopyData, PQputCopyEnd, PQresultErrorField,
+    PQresultStatus, PQresultVerboseErrorMessage, PQsendQuery, PQsendQueryParams,
+    PQsendQueryPrepared, PQsetErrorVerbosity, PQsetNoticeReceiver, PQsetSingleRowMode,
+    PQsetnonblocking, PQsocket, PQsslInUse, PQsslStruct, PQstatus, PQtransactionStatus,
+};
+
+use crate::cancel::{CancelHandle, QueryTimeout};
+use crate::config::{ConnectionConfig, Host, LoadBalanceHosts};
+use crate::connection::{ColumnDescription, QueryResult, StatementDescription, StatementResult};
+use crate::error::{DbError, DbErrorKind, StatementError};
+use crate::notices::{notice_receiver, Notice, NoticeStorage, Verbosity};
+use crate::params::{Oid, ToSql};
+use crate::pipeline::{Pipeline, PipelineResult};
+use crate::server_info::{self, ServerInfo, ServerVersion};
+use crate::statement::{Statement, StatementCache};
+use crate::tls::{self, CertificateUnavailable, FingerprintMismatch, TlsInfo};
+use crate::value::Value;
+
+/// A single open connection to the server.
+///
+/// A session owns the libpq connection together with the storage its notice
+/// receiver writes into, and executes queries on that connection.
+pub(crate) struct Session {
+    // Declared before `notices` so the connection is finished before the
+    // notice storage the receiver points at is released
+    conn: Connection,
+    notices: NoticeStorage,
+    verbosity: Verbosity,
+    server: Host,
+    tls_info: Option<TlsInfo>,
+    cancel: Option<Arc<CancelHandle>>,
+    // Set when a timed out query could not be stopped, leaving the connection busy
+    broken: AtomicBool,
+    // Named statements prepared on this connection, by name, with their SQL text
+    prepared: Mutex<HashMap<String, String>>,
+    statement_cache: Mutex<StatementCache>,
+}
+
+// Field code of the SQLSTATE in PQresultErrorField (PG_DIAG_SQLSTATE in postgres_ext.h)
+const PG_DIAG_SQLSTATE: i32 = b'C' as i32;
+
+// How long to wait for the server to stop a timed out query after cancelling it
+const CANCEL_GRACE: Duration = Duration::from_secs(5);
+
+// Helper function to safely clear a PGresult and log it
+fn clear_pg_result(result: *mut libpq_sys::PGresult) {
+    if !result.is_null() {
+        unsafe {
+            debug!("Clearing PGresult at {:p}", result);
+            PQclear(result);
+            debug!("PGresult cleared successfully");
+        }
+    }
+}
+
+// Column names and rows read from a PGresult
+type Rows = (Vec<String>, Vec<HashMap<String, Value>>);
+
+// Reads the column names and rows of a successful PGresult
+fn read_rows(result: *mut libpq_sys::PGresult) -> Rows {
+    // Get column information
+    debug!("Getting column count");
+    let col_count = unsafe { PQnfields(result) };
+
+    // Create a vector to store column names
+    debug!("Getting column names");
+    let mut column_names = Vec::with_capacity(col_count as usize);
+    for col_index in 0..col_count {
+        let col_name_ptr = unsafe { PQfname(result, col_index) };
+        if !col_name_ptr.is_null() {
+            let col_name = unsafe { CStr::from_ptr(col_name_ptr).to_string_lossy().into_owned() };
+            column_names.push(col_name);
+        } else {
+            column_names.push(String::from("(unknown)"));
+        }
+    }
+
+    // Initialize row_count here; results without rows report 0
+    debug!("Getting row count");
+    let row_count = unsafe { PQntuples(result) };
+
+    // Create the rows vector
+    let mut rows = Vec::new();
+
+    // Get row data if available
+    if row_count > 0 {
+        debug!("Processing rows");
+
+        // Process each row
+        for row_index in 0..row_count {
+            let mut row_data = HashMap::new();
+
+            // Process each column in the row
+            for col_index in 0..col_count {
+                let value_ptr = unsafe { PQgetvalue(result, row_index, col_index) };
+                let value = if !value_ptr.is_null() {
+                    let string_value =
+                        unsafe { CStr::from_ptr(value_ptr).to_string_lossy().into_owned() };
+                    Value::String(string_value)
+                } else {
+                    Value::Null
+                };
+
+                // Insert value into the row map using the column name as key
+                row_data.insert(column_names[col_index as usize].clone(), value);
+            }
+
+            rows.push(row_data);
+        }
+    }
+    debug!("Rows processed: {}", rows.len());
+
+    (column_names, rows)
+}
+
+// Type OIDs of query parameters
+fn param_types(params: &[&dyn ToSql]) -> Vec<Oid> {
+    params.iter().map(|param| param.type_oid()).collect()
+}
+
+// Text values of query parameters, `None` for NULL
+fn param_values(params: &[&dyn ToSql]) -> Result<Vec<Option<CString>>, NulError> {
+    params
+        .iter()
+        .map(|param| param.to_sql_text().map(CString::new).transpose())
+        .collect()
+}
+
+// Pointers to parameter values for libpq, which must not outlive `values`
+fn value_pointers(values: &[Option<CString>]) -> Vec<*const c_char> {
+    values
+        .iter()
+        .map(|value| value.as_ref().map_or(ptr::null(), |value| value.as_ptr()))
+        .collect()
+}
+
+// Whether an error means a prepared statement must be prepared again: it no
+// longer exists on the server, or its result type changed with the schema
+fn is_stale_statement(error: &(dyn std::error::Error + 'static)) -> bool {
+    error
+        .downcast_ref::<DbError>()
+        .and_then(|e| e.sqlstate.as_deref())
+        .is_some_and(|code| code == "26000" || code == "0A000")
+}
+
+// PQresultStatus as a plain integer, for result statuses newer than the
+// ExecStatusType bindings (PGRES_TUPLES_CHUNK)
+extern "C" {
+    #[link_name = "PQresultStatus"]
+    fn PQresultStatus_raw(res: *const libpq_sys::PGresult) -> c_int;
+}
+
+const RAW_TUPLES_OK: c_int = PGRES_TUPLES_OK as c_int;
+const RAW_COMMAND_OK: c_int = PGRES_COMMAND_OK as c_int;
+const RAW_SINGLE_TUPLE: c_int = PGRES_SINGLE_TUPLE as c_int;
+const RAW_COPY_IN: c_int = PGRES_COPY_IN as c_int;
+const RAW_COPY_OUT: c_int = PGRES_COPY_OUT as c_int;
+const RAW_TUPLES_CHUNK: c_int = 12;
+
+// Rows per result when streaming in chunked-rows mode
+const ROW_CHUNK_SIZE: c_int = 256;
+
+type SetChunkedRowsMode = unsafe extern "C" fn(*mut libpq_sys::PGconn, c_int) -> c_int;
+
+// PQsetChunkedRowsMode, added in libpq 17, looked up at run time so older
+// versions of libpq can still be linked
+#[cfg(unix)]
+fn chunked_rows_mode() -> Option<SetChunkedRowsMode> {
+    static FUNCTION: OnceLock<Option<SetChunkedRowsMode>> = OnceLock::new();
+    *FUNCTION.get_or_init(|| {
+        if unsafe { PQlibVersion() } < 170000 {
+            return None;
+        }
+        unsafe {
+            let program = libc::dlopen(ptr::null(), libc::RTLD_LAZY);
+            if program.is_null() {
+                return None;
+            }
+            let symbol = libc::dlsym(program, c"PQsetChunkedRowsMode".as_ptr());
+            (!symbol.is_null())
+                .then(|| std::mem::transmute::<*mut c_void, SetChunkedRowsMode>(symbol))
+        }
+    })
+}
+
+#[cfg(not(unix))]
+fn chunked_rows_mode() -> Option<SetChunkedRowsMode> {
+    None
+}
+
+// Host and port of the server the connection was made to, from PQhost/PQport
+fn connected_server(conn: &Connection) -> Host {
+    unsafe {
+        let host_ptr = PQhost(conn.into());
+        let port_ptr = PQport(conn.into());
+        let host = if host_ptr.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(host_ptr).to_string_lossy().into_owned()
+        };
+        let port = if port_ptr.is_null() {
+            0
+        } else {
+            CStr::from_ptr(port_ptr)
+                .to_string_lossy()
+                .parse()
+                .unwrap_or(0)
+        };
+        Host { host, port }
+    }
+}
+
+// Wait up to `timeout` (or indefinitely for `None`) for the connection's socket to become readable
+#[cfg(unix)]
+pub(crate) fn wait_readable(conn: &Connection, timeout: Option<Duration>) -> bool {
+    wait_socket(conn, libc::POLLIN, timeout)
+}
+
+// Wait up to `timeout` for the connection's socket to become writable
+#[cfg(unix)]
+fn wait_writable(conn: &Connection, timeout: Option<Duration>) -> bool {
+    wait_socket(conn, libc::POLLOUT, timeout)
+}
+
+// Wait up to `timeout` for the connection's socket to become readable or writable
+#[cfg(unix)]
+fn wait_readable_or_writable(conn: &Connection, timeout: Option<Duration>) -> bool {
+    wait_socket(conn, libc::POLLIN | libc::POLLOUT, timeout)
+}
+
+#[cfg(unix)]
+fn wait_socket(conn: &Connection, events: libc::c_short, timeout: Option<Duration>) -> bool {
+    let fd = unsafe { PQsocket(conn.into()) };
+    if fd < 0 {
+        return false;
+    }
+    let mut pollfd = libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+    let timeout_ms = match timeout {
+        Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as i32,
+        None => -1,
+    };
+    unsafe { libc::poll(&mut pollfd, 1, timeout_ms) > 0 }
+}
+
+// Without poll(2), pause briefly so polling callers do not spin, then assume data
+// may be waiting so they fall back to libpq's reads
+#[cfg(not(unix))]
+pub(crate) fn wait_readable(_conn: &Connection, timeout: Option<Duration>) -> bool {
+    let pause = Duration::from_millis(10);
+    std::thread::sleep(timeout.map_or(pause, |timeout| timeout.min(pause)));
+    true
+}
+
+#[cfg(not(unix))]
+fn wait_writable(conn: &Connection, timeout: Option<Duration>) -> bool {
+    wait_readable(conn, timeout)
+}
+
+#[cfg(not(unix))]
+fn wait_readable_or_writable(conn: &Connection, timeout: Option<Duration>) -> bool {
+    wait_readable(conn, timeout)
+}
+
+// Emulates random load_balance_hosts on libpq older than 16, which ignores it.
+pub(crate) fn balanced_config(config: &ConnectionConfig) -> Cow<'_, ConnectionConfig> {
+    if config.get_load_balance_hosts() == Some(LoadBalanceHosts::Random)
+        && unsafe { PQlibVersion() } < 160000
+    {
+        Cow::Owned(config.with_shuffled_hosts())
+    } else {
+        Cow::Borrowed(config)
+    }
+}
+
+// Adjusts the configuration for a single connection attempt: balances the hosts
+// and fills in provider credentials.
+fn effective_config(
+    config: &ConnectionConfig,
+) -> Result<Cow<'_, ConnectionConfig>, Box<dyn std::error::Error>> {
+    let mut effective = balanced_config(config);
+    if let Some(cache) = config.credential_cache() {
+        let fetched = cache.get()?;
+        effective = Cow::Owned(effective.with_credentials(&fetched));
+    }
+    Ok(effective)
+}
+
+// Opens a connection with libpq. With pinned certificates the connection is driven
+// step by step so the server certificate is checked as soon as the TLS handshake
+// completes, before any credentials are sent.
+fn open_connection(
+    config: &ConnectionConfig,
+    conn_str: &str,
+) -> Result<Connection, Box<dyn std::error::Error>> {
+    if config.get_tls().get_pinned_fingerprints().is_empty() {
+        return Connection::new(conn_str)
+            .map_err(|e| DbError::new(DbErrorKind::Connect, &e.to_string()).into());
+    }
+    if config.get_hosts().len() <= 1 {
+        return open_pinned(config, conn_str);
+    }
+
+    // libpq cannot be told to give up on a host whose certificate matches no pin,
+    // so the hosts are tried one at a time; a mismatch is only reported if no
+    // other host accepts the connection
+    let ordered = if config.get_load_balance_hosts() == Some(LoadBalanceHosts::Random) {
+        Cow::Owned(config.with_shuffled_hosts())
+    } else {
+        Cow::Borrowed(config)
+    };
+    let mut mismatch = None;
+    let mut last_error = None;
+    for host in ordered.get_hosts() {
+        let single = ordered.as_ref().clone().hosts(vec![host.clone()]);
+        match open_pinned(&single, &single.to_conninfo()) {
+            Ok(conn) => return Ok(conn),
+            Err(e) if e.is::<FingerprintMismatch>() || e.is::<CertificateUnavailable>() => {
+                debug!("Pinned certificate check failed for {}: {}", host, e);
+                mismatch.get_or_insert(e);
+            }
+            Err(e) => {
+                debug!("Connection to {} failed: {}", host, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(mismatch
+        .or(last_error)
+        .expect("at least one host was tried"))
+}
+
+// Opens a connection to the hosts of `conn_str` with libpq, failing as soon as a
+// server certificate matches none of the pinned fingerprints
+fn open_pinned(
+    config: &ConnectionConfig,
+    conn_str: &str,
+) -> Result<Connection, Box<dyn std::error::Error>> {
+    let connect_error = |message: &str| DbError::new(DbErrorKind::Connect, message);
+
+    let tls = config.get_tls();
+    let conn = Connection::start(conn_str).map_err(|e| connect_error(&e.to_string()))?;
+
+    // PQconnectPoll leaves connect_timeout to the caller; allow it once per host
+    let deadline = config
+        .get_connect_timeout()
+        .filter(|&secs| secs > 0)
+        .map(|secs| {
+            Instant::now() + Duration::from_secs(u64::from(secs) * config.get_hosts().len() as u64)
+        });
+
+    // libpq moves on to the next host after a failure, so every new TLS session is
+    // checked once its peer certificate is available. A certificate that still cannot
+    // be read once the handshake is over fails the check before authentication starts.
+    let mut checked_ssl: *mut c_void = ptr::null_mut();
+    let mut status = libpq::poll::Status::Writing;
+    loop {
+        if unsafe { PQsslInUse((&conn).into()) } != 0 {
+            let ssl = unsafe { PQsslStruct((&conn).into(), c"OpenSSL".as_ptr()) };
+            if ssl != checked_ssl {
+                let info = tls::tls_info(&conn);
+                let handshake_done = unsafe { PQstatus((&conn).into()) } != CONNECTION_SSL_STARTUP;
+                if handshake_done
+                    || info
+                        .as_ref()
+                        .is_some_and(|info| info.peer_certificate.is_some())
+                {
+                    tls.check_pins(info.as_ref())?;
+                    checked_ssl = ssl;
+                }
+            }
+        }
+
+        let remaining = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(connect_error("timeout expired").into());
+                }
+                Some(deadline - now)
+            }
+            None => None,
+        };
+        match status {
+            libpq::poll::Status::Ok => {
+                // Covers connections that completed without a TLS session to check
+                if checked_ssl.is_null() {
+                    tls.check_pins(tls::tls_info(&conn).as_ref())?;
+                }
+                return Ok(conn);
+            }
+            libpq::poll::Status::Failed => {
+                let message = conn.error_message().unwrap_or("Unknown error");
+                return Err(connect_error(message.trim_end()).into());
+            }
+            libpq::poll::Status::Reading => {
+                wait_readable(&conn, remaining);
+            }
+            libpq::poll::Status::Writing => {
+                wait_writable(&conn, remaining);
+            }
+            libpq::poll::Status::Active => {}
+        }
+        status = conn.poll();
+    }
+}
+
+impl Session {
+    /// Opens a new connection using the given configuration.
+    pub(crate) fn connect(config: &ConnectionConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let credentials = config.credential_cache();
+        let effective = effective_config(config)?;
+        let config = effective.as_ref();
+
+        // Create a connection string
+        let conn_str = config.to_conninfo();
+        debug!(
+            "Establishing connection using: {}",
+            config.to_redacted_conninfo()
+        );
+
+        let conn = match open_connection(config, &conn_str) {
+            Ok(conn) => conn,
+            Err(e) => {
+                // The credentials may have been revoked or expired early, so fetch
+                // fresh ones for the next attempt
+                if let Some(cache) = credentials {
+                    if e.is::<DbError>() {
+                        cache.invalidate();
+                    }
+                }
+                return Err(e);
+            }
+        };
+
+        // Record which server in the host list accepted the connection
+        let server = connected_server(&conn);
+
+        // Connection diagnostics
+        unsafe {
+            let ssl_in_use = libpq_sys::PQsslInUse((&conn).into()) != 0;
+            debug!("Connected to: {} (ssl: {})", server, ssl_in_use);
+
+            // PQstatus output
+            let status = libpq_sys::PQstatus((&conn).into());
+            debug!("Connection status: {:?}", status);
+
+            // PQtransactionStatus output
+            let tx_status = libpq_sys::PQtransactionStatus((&conn).into());
+            debug!("Transaction status: {:?}", tx_status);
+
+            // PQserverVersion output
+            let server_version = libpq_sys::PQserverVersion((&conn).into());
+            match ServerVersion::from_number(server_version) {
+                Some(version) => debug!("Server version: {} ({})", version, server_version),
+                None => debug!("Server version: unknown ({})", server_version),
+            }
+        }
+
+        // Record the negotiated TLS parameters; pinned certificates were already
+        // checked during the handshake
+        let tls_info = tls::tls_info(&conn);
+        if let Some(info) = &tls_info {
+            debug!(
+                "TLS session: protocol {:?}, cipher {:?}, key bits {:?}",
+                info.protocol, info.cipher, info.key_bits
+            );
+        }
+
+        // Apply the desired verbosity level
+        let verbosity = config.get_verbosity();
+        debug!("Setting error verbosity to: {:?}", verbosity);
+        unsafe {
+            PQsetErrorVerbosity((&conn).into(), verbosity.into());
+        }
+
+        // Set up notice receiver for the connection; the storage lives as long
+        // as the session, which outlives the connection
+        debug!("Setting up notice receiver");
+        let notices: NoticeStorage = Arc::new(Mutex::new(Vec::new()));
+        let notices_ptr = Arc::as_ptr(&notices) as *mut c_void;
+        unsafe {
+            PQsetNoticeReceiver((&conn).into(), Some(notice_receiver), notices_ptr);
+        }
+
+        let cancel = CancelHandle::new(&conn).map(Arc::new);
+
+        let session = Session {
+            conn,
+            notices,
+            verbosity,
+            server,
+            tls_info,
+            cancel,
+            broken: AtomicBool::new(false),
+            prepared: Mutex::new(HashMap::new()),
+            statement_cache: Mutex::new(StatementCache::new(config.get_statement_cache_size())),
+        };
+
+        // Run the configured session setup; a failure here means the connection is unusable
+        for statement in config.get_init_statements() {
+            debug!("Running initialization statement: {}", statement);
+            if let Err(e) = session.query(statement, None) {
+                // Keep the SQLSTATE, which the retry policy uses to tell a bad
+                // statement from a transient failure
+                let sqlstate = e.downcast_ref::<DbError>().and_then(|e| e.sqlstate.clone());
+                return Err(DbError {
+                    kind: DbErrorKind::Connect,
+                    message: format!("Initialization statement \"{}\" failed: {}", statement, e),
+                    sqlstate,
+                }
+                .into());
+            }
+        }
+
+        Ok(session)
+    }
+
+    /// Returns the server that accepted the connection.
+    pub(crate) fn server(&self) -> &Host {
+        &self.server
+    }
+
+    /// Returns the handle used to cancel queries running on this connection.
+    pub(crate) fn cancel_handle(&self) -> Option<&Arc<CancelHandle>> {
+        self.cancel.as_ref()
+    }
+
+    /// Returns the server information, with parameter values as currently reported.
+    pub(crate) fn server_info(&self) -> ServerInfo {
+        server_info::server_info(&self.conn)
+    }
+
+    /// Returns the TLS session details recorded when the connection was opened.
+    pub(crate) fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls_info.as_ref()
+    }
+
+    /// Checks whether the connection is still usable.
+    ///
+    /// Reads everything the server has sent while the connection was idle, so that
+    /// a connection terminated by the server (which sends a FATAL message and then
+    /// closes the socket) is detected, then checks `PQstatus`.
+    pub(crate) fn is_healthy(&self) -> bool {
+        if self.broken.load(Ordering::Relaxed) {
+            return false;
+        }
+        if unsafe { PQstatus((&self.conn).into()) } != CONNECTION_OK {
+            return false;
+        }
+        while wait_readable(&self.conn, Some(Duration::ZERO)) {
+            if unsafe { PQconsumeInput((&self.conn).into()) } == 0 {
+                debug!(
+                    "Connection check failed: {}",
+                    self.conn
+                        .error_message()
+                        .unwrap_or("Unknown error")
+                        .trim_end()
+                );
+                return false;
+            }
+            if cfg!(not(unix)) {
+                break;
+            }
+        }
+        unsafe { PQstatus((&self.conn).into()) == CONNECTION_OK }
+    }
+
+    /// Checks the connection with a round trip to the server.
+    ///
+    /// Sends an empty query, which the server answers without doing any work.
+    pub(crate) fn ping(&self) -> bool {
+        if !self.is_healthy() {
+            return false;
+        }
+        unsafe {
+            let result = PQexec((&self.conn).into(), c"".as_ptr());
+            let ok = !result.is_null() && PQresultStatus(result) == PGRES_EMPTY_QUERY;
+            clear_pg_result(result);
+            ok
+        }
+    }
+
+    /// Rolls back a transaction left open on the connection.
+    ///
+    /// Returns `false` if the connection is busy or the rollback failed, in which
+    /// case the connection should not be reused.
+    pub(crate) fn reset_transaction(&self) -> bool {
+        match unsafe { PQtransactionStatus((&self.conn).into()) } {
+            PQTRANS_IDLE => true,
+            PQTRANS_INTRANS | PQTRANS_INERROR => {
+                debug!("Rolling back transaction left open on the connection");
+                unsafe {
+                    let result = PQexec((&self.conn).into(), c"ROLLBACK".as_ptr());
+                    let ok = !result.is_null() && PQresultStatus(result) == PGRES_COMMAND_OK;
+                    clear_pg_result(result);
+                    ok
+                }
+            }
+            _ => false,
+        }
+    }
+
+    // Waits until the connection is no longer busy or `deadline` passes; returns
+    // whether the connection became ready
+    fn wait_until_ready(&self, deadline: Instant) -> bool {
+        loop {
+            unsafe {
+                if PQconsumeInput((&self.conn).into()) == 0 || PQisBusy((&self.conn).into()) == 0 {
+                    // Ready, or failed in a way PQgetResult will report
+                    return true;
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            wait_readable(&self.conn, Some(deadline - now));
+        }
+    }
+
+    // Cancels a query that ran past its deadline and drains the connection
+    fn cancel_timed_out_query(&self, started: Instant) -> QueryTimeout {
+        let elapsed = started.elapsed();
+        debug!("Query timed out after {:?}, cancelling it", elapsed);
+
+        let sent = match &self.cancel {
+            Some(handle) => match handle.cancel() {
+                Ok(()) => true,
+                Err(e) => {
+                    debug!("Cancel request failed: {}", e);
+                    false
+                }
+            },
+            None => false,
+        };
+
+        let cancel_acknowledged = sent && self.wait_until_ready(Instant::now() + CANCEL_GRACE);
+        if cancel_acknowledged {
+            self.consume_pending_results();
+        } else {
+            debug!("Server did not stop the timed out query, discarding the connection");
+            self.broken.store(true, Ordering::Relaxed);
+        }
+
+        QueryTimeout {
+            elapsed,
+            cancel_acknowledged,
+        }
+    }
+
+    // Classifies a failed statement by whether the connection survived it
+    fn failure_kind(&self) -> DbErrorKind {
+        if unsafe { PQstatus((&self.conn).into()) } == CONNECTION_OK {
+            DbErrorKind::Server
+        } else {
+            DbErrorKind::ConnectionLost
+        }
+    }
+
+    // Helper method to consume any pending results
+    fn consume_pending_results(&self) {
+        debug!("Consuming pending results");
+        unsafe {
+            // First make sure we've read all data available from the server
+            PQconsumeInput((&self.conn).into());
+
+            // Then clear any pending results
+            loop {
+                let result = PQgetResult((&self.conn).into());
+                if result.is_null() {
+                    break;
+                }
+                // May be a chunk left over from a streamed query
+                let status = PQresultStatus_raw(result);
+                clear_pg_result(result);
+
+                // libpq keeps returning the COPY result until the copy is ended
+                if status == RAW_COPY_IN {
+                    debug!("Ending unexpected COPY FROM STDIN");
+                    let message = c"COPY FROM STDIN is not supported by this call";
+                    if PQputCopyEnd((&self.conn).into(), message.as_ptr()) != 1 {
+                        self.broken.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                } else if status == RAW_COPY_OUT && !self.stop_copy_out() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Builds the result of one statement from a successful PGresult, taking the
+    // notices collected so far; the PGresult is not freed
+    fn statement_result(
+        &self,
+        result: *mut libpq_sys::PGresult,
+        started: Instant,
+    ) -> StatementResult {
+        let status = unsafe { PQresultStatus(result) };
+        let (column_names, rows) = read_rows(result);
+        let command_tag = unsafe { CStr::from_ptr(PQcmdStatus(result)) }
+            .to_string_lossy()
+            .into_owned();
+        let rows_affected = unsafe { CStr::from_ptr(PQcmdTuples(result)) }
+            .to_string_lossy()
+            .parse()
+            .ok();
+
+        // Notices raised by a statement arrive before its result
+        let notices = self.take_notices();
+
+        StatementResult {
+            col_count: column_names.len() as i32,
+            row_count: rows.len() as i32,
+            rows,
+            column_names,
+            notices,
+            status,
+            command_tag,
+            rows_affected,
+            elapsed_time_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+
+    // Removes and returns the notices collected since they were last taken
+    fn take_notices(&self) -> Vec<Notice> {
+        debug!("Collecting notices");
+        match self.notices.lock() {
+            Ok(mut notices) => notices.drain(..).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // Builds the error for a failed PGresult
+    fn result_error(&self, result: *mut libpq_sys::PGresult) -> DbError {
+        // Try to get a detailed error message
+        let error_msg_ptr = unsafe {
+            PQresultVerboseErrorMessage(
+                result,
+                self.verbosity.into(),
+                PGContextVisibility::PQSHOW_CONTEXT_ALWAYS,
+            )
+        };
+
+        let error_msg = if !error_msg_ptr.is_null() {
+            // Convert the C string to a Rust string
+            let msg = unsafe { CStr::from_ptr(error_msg_ptr).to_string_lossy().into_owned() };
+            // Free the C string allocated by PQresultVerboseErrorMessage
+            unsafe { libpq_sys::PQfreemem(error_msg_ptr as *mut _) };
+            msg
+        } else {
+            // Fallback to the standard connection error message if verbose message is not available
+            self.conn
+                .error_message()
+                .unwrap_or("Unknown error")
+                .to_string()
+        };
+
+        // SQLSTATE of the error, absent for errors raised by libpq itself
+        let sqlstate_ptr = unsafe { PQresultErrorField(result, PG_DIAG_SQLSTATE) };
+        let sqlstate = if sqlstate_ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(sqlstate_ptr).to_string_lossy().into_owned() })
+        };
+
+        DbError {
+            kind: self.failure_kind(),
+            message: error_msg.trim_end().to_string(),
+            sqlstate,
+        }
+    }
+
+    /// Executes a SQL query on this connection and returns the results.
+    ///
+    /// With a `timeout`, the query is cancelled if it has not completed in time.
+    pub(crate) fn query(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        // Clear any previous notices
+        self.clear_notices();
+
+        let start_time = Instant::now();
+
+        // add ; to `query` if it doesn't end with one
+        let query = if query.ends_with(';') {
+            query.to_string()
+        } else {
+            format!("{};", query)
+        };
+        let c_query = CString::new(query.as_str())?;
+
+        // Use PQsendQuery
+        debug!("Sending query: {}", query);
+        let send_success = unsafe { PQsendQuery((&self.conn).into(), c_query.as_ptr()) };
+        if send_success == 0 {
+            return Err(self.send_error().into());
+        }
+
+        self.read_query_result(start_time, timeout)
+    }
+
+    /// Executes a SQL query with parameters on this connection and returns the results.
+    ///
+    /// The parameters are sent separately from the query text, referenced as `$1`,
+    /// `$2` and so on. Only a single statement is allowed.
+    pub(crate) fn query_params(
+        &self,
+        query: &str,
+        params: &[&dyn ToSql],
+        timeout: Option<Duration>,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        self.clear_notices();
+        let start_time = Instant::now();
+
+        let c_query = CString::new(query)?;
+        let types = param_types(params);
+        let values = param_values(params)?;
+        let value_ptrs = value_pointers(&values);
+
+        debug!("Sending query with {} parameters: {}", params.len(), query);
+        let send_success = unsafe {
+            PQsendQueryParams(
+                (&self.conn).into(),
+                c_query.as_ptr(),
+                params.len() as i32,
+                types.as_ptr(),
+                value_ptrs.as_ptr(),
+                ptr::null(),
+                ptr::null(),
+                0,
+            )
+        };
+        if send_success == 0 {
+            return Err(self.send_error().into());
+        }
+
+        self.read_query_result(start_time, timeout)
+    }
+
+    // The error for a query that could not be sent
+    fn send_error(&self) -> DbError {
+        let message = format!(
+            "Error: {}",
+            self.conn.error_message().unwrap_or("Unknown error")
+        );
+        DbError::new(self.failure_kind(), &message)
+    }
+
+    // Removes notices left over from a previous query
+    fn clear_notices(&self) {
+        debug!("Clearing previous notices");
+        if let Ok(mut notices) = self.notices.lock() {
+            notices.clear();
+        }
+    }
+
+    // Waits for the result of a sent query and reads it; later results are discarded
+    fn read_query_result(
+        &self,
+        start_time: Instant,
+        timeout: Option<Duration>,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        // Wait for the result without blocking past the deadline
+        if let Some(timeout) = timeout {
+            if !self.wait_until_ready(start_time + timeout) {
+                return Err(self.cancel_timed_out_query(start_time).into());
+            }
+        }
+
+        // Process the result
+        debug!("Processing the result");
+        let result = unsafe { PQgetResult((&self.conn).into()) };
+
+        if result.is_null() {
+            return Err("No result returned".into());
+        }
+
+        let status = unsafe { PQresultStatus(result) };
+
+        if status == PGRES_COPY_IN {
+            clear_pg_result(result);
+            self.consume_pending_results();
+            return Err("COPY FROM STDIN must be run with copy_in()".into());
+        }
+
+        if status == PGRES_COPY_OUT {
+            clear_pg_result(result);
+            self.consume_pending_results();
+            return Err("COPY TO STDOUT must be run with copy_out()".into());
+        }
+
+        if status != PGRES_TUPLES_OK && status != PGRES_COMMAND_OK {
+            let error = self.result_error(result);
+            clear_pg_result(result);
+
+            // Clear any pending results
+            self.consume_pending_results();
+
+            return Err(error.into());
+        }
+
+        let (column_names, rows) = read_rows(result);
+        let col_count = column_names.len() as i32;
+        let row_count = rows.len() as i32;
+
+        clear_pg_result(result);
+
+        // Check for any remaining results and clear them
+        self.consume_pending_results();
+
+        // Get the notices that were collected during the query
+        let notices = self.take_notices();
+        let notice_count = notices.len();
+
+        let elapsed_time_ms = start_time.elapsed().as_millis() as u64;
+
+        Ok(QueryResult {
+            rows,
+            column_names,
+            notices,
+            row_count,
+            col_count,
+            notice_count,
+            status,
+            elapsed_time_ms,
+            attempts: 1,
+            server: self.server.clone(),
+            tls: self.tls_info.clone(),
+        })
+    }
+
+    /// Executes a string of one or more SQL statements and returns one result per statement.
+    ///
+    /// Stops at the first failing statement; the server skips the statements after it.
+    pub(crate) fn query_multi(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<StatementResult>, Box<dyn std::error::Error>> {
+        self.clear_notices();
+
+        let start_time = Instant::now();
+        let c_query = CString::new(query)?;
+
+        debug!("Sending statements: {}", query);
+        let send_success = unsafe { PQsendQuery((&self.conn).into(), c_query.as_ptr()) };
+        if send_success == 0 {
+            return Err(self.send_error().into());
+        }
+
+        let mut results = Vec::new();
+        let mut statement_start = start_time;
+        loop {
+            // Wait for each statement without blocking past the shared deadline
+            if let Some(timeout) = timeout {
+                if !self.wait_until_ready(start_time + timeout) {
+                    return Err(self.cancel_timed_out_query(start_time).into());
+                }
+            }
+
+            let result = unsafe { PQgetResult((&self.conn).into()) };
+            if result.is_null() {
+                break;
+            }
+
+            let status = unsafe { PQresultStatus(result) };
+            if status == PGRES_EMPTY_QUERY {
+                clear_pg_result(result);
+                continue;
+            }
+            if status != PGRES_TUPLES_OK && status != PGRES_COMMAND_OK {
+                let error = self.result_error(result);
+                clear_pg_result(result);
+                self.consume_pending_results();
+                return Err(StatementError {
+                    index: results.len(),
+                    error,
+                    results,
+                }
+                .into());
+            }
+
+            let statement = self.statement_result(result, statement_start);
+            clear_pg_result(result);
+            debug!(
+                "Statement {} completed: {}",
+                results.len(),
+                statement.command_tag
+            );
+            results.push(statement);
+            statement_start = Instant::now();
+        }
+
+        Ok(results)
+    }
+
+    /// Prepares a named statement on this connection, unless it already is.
+    ///
+    /// A statement of the same name with different SQL text is replaced.
+    pub(crate) fn prepare(&self, statement: &Statement) -> Result<(), Box<dyn std::error::Error>> {
+        let mut prepared = self
+            .prepared
+            .lock()
+            .map_err(|_| "Prepared statement lock poisoned")?;
+        match prepared.get(statement.name()) {
+            Some(sql) if sql == statement.sql() => return Ok(()),
+            Some(_) => {
+                self.deallocate(statement.name());
+                prepared.remove(statement.name());
+            }
+            None => {}
+        }
+        self.prepare_on_server(statement.name(), statement.sql(), &[])?;
+        prepared.insert(statement.name().to_string(), statement.sql().to_string());
+        Ok(())
+    }
+
+    /// Executes a named prepared statement, preparing it on this connection first
+    /// if needed.
+    pub(crate) fn query_prepared(
+        &self,
+        statement: &Statement,
+        params: &[&dyn ToSql],
+        timeout: Option<Duration>,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        self.prepare(statement)?;
+        match self.execute_prepared(statement.name(), params, timeout) {
+            Err(e) if is_stale_statement(e.as_ref()) && self.is_idle() => {
+                debug!(
+                    "Prepared statement {} is stale, preparing it again",
+                    statement.name()
+                );
+                if let Ok(mut prepared) = self.prepared.lock() {
+                    prepared.remove(statement.name());
+                }
+                self.deallocate(statement.name());
+                self.prepare(statement)?;
+                self.execute_prepared(statement.name(), params, timeout)
+            }
+            result => result,
+        }
+    }
+
+    /// Executes a SQL query with parameters through a statement from the
+    /// connection's statement cache, preparing and caching it on first use.
+    pub(crate) fn query_cached(
+        &self,
+        query: &str,
+        params: &[&dyn ToSql],
+        timeout: Option<Duration>,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        let types = param_types(params);
+        let name = self.cached_statement(query, &types)?;
+        match self.execute_prepared(&name, params, timeout) {
+            Err(e) if is_stale_statement(e.as_ref()) && self.is_idle() => {
+                debug!("Cached statement {} is stale, preparing it again", name);
+                if let Ok(mut cache) = self.statement_cache.lock() {
+                    cache.remove(query, &types);
+                }
+                self.deallocate(&name);
+                let name = self.cached_statement(query, &types)?;
+                self.execute_prepared(&name, params, timeout)
+            }
+            result => result,
+        }
+    }
+
+    // Returns the name of the cached statement for `query`, preparing it if needed
+    fn cached_statement(
+        &self,
+        query: &str,
+        types: &[Oid],
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut cache = self
+            .statement_cache
+            .lock()
+            .map_err(|_| "Statement cache lock poisoned")?;
+        if let Some(name) = cache.get(query, types) {
+            return Ok(name);
+        }
+
+        let name = cache.next_name();
+        self.prepare_on_server(&name, query, types)?;
+        if let Some(evicted) = cache.insert(query, types, name.clone()) {
+            debug!("Evicting statement {} from the statement cache", evicted);
+            self.deallocate(&evicted);
+        }
+        Ok(name)
+    }
+
+    // Whether the connection is idle outside a transaction
+    fn is_idle(&self) -> bool {
+        unsafe { PQtransactionStatus((&self.conn).into()) == PQTRANS_IDLE }
+    }
+
+    // Prepares `query` on the server as the statement `name`
+    fn prepare_on_server(
+        &self,
+        name: &str,
+        query: &str,
+        types: &[Oid],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let c_name = CString::new(name)?;
+        let c_query = CString::new(query)?;
+
+        debug!("Preparing statement {}: {}", name, query);
+        let result = unsafe {
+            PQprepare(
+                (&self.conn).into(),
+                c_name.as_ptr(),
+                c_query.as_ptr(),
+                types.len() as i32,
+                if types.is_empty() {
+                    ptr::null()
+                } else {
+                    types.as_ptr()
+                },
+            )
+        };
+        if result.is_null() {
+            return Err(self.send_error().into());
+        }
+
+        let status = unsafe { PQresultStatus(result) };
+        let outcome = if status == PGRES_COMMAND_OK {
+            Ok(())
+        } else {
+            Err(self.result_error(result).into())
+        };
+        clear_pg_result(result);
+        outcome
+    }
+
+    // Releases a prepared statement on the server; failures only leave it allocated
+    fn deallocate(&self, name: &str) {
+        let sql = format!("DEALLOCATE \"{}\"", name.replace('"', "\"\""));
+        let Ok(c_sql) = CString::new(sql) else {
+            return;
+        };
+        debug!("Deallocating statement {}", name);
+        unsafe {
+            let result = PQexec((&self.conn).into(), c_sql.as_ptr());
+            if result.is_null() || PQresultStatus(result) != PGRES_COMMAND_OK {
+                debug!("Failed to deallocate statement {}", name);
+            }
+            clear_pg_result(result);
+        }
+    }
+
+    // Executes the prepared statement `name` with parameters
+    fn execute_prepared(
+        &self,
+        name: &str,
+        params: &[&dyn ToSql],
+        timeout: Option<Duration>,
+    ) -> Result<QueryResult, Box<dyn std::error::Error>> {
+        self.clear_notices();
+        let start_time = Instant::now();
+
+        let c_name = CString::new(name)?;
+        let values = param_values(params)?;
+        let value_ptrs = value_pointers(&values);
+
+        debug!(
+            "Executing prepared statement {} with {} parameters",
+            name,
+            params.len()
+        );
+        let send_success = unsafe {
+            PQsendQueryPrepared(
+                (&self.conn).into(),
+                c_name.as_ptr(),
+                params.len() as i32,
+                value_ptrs.as_ptr(),
+                ptr::null(),
+                ptr::null(),
+                0,
+            )
+        };
+        if send_success == 0 {
+            return Err(self.send_error().into());
+        }
+
+        self.read_query_result(start_time, timeout)
+    }
+
+    /// Describes a statement by preparing it as the unnamed statement.
+    pub(crate) fn describe(
+        &self,
+        query: &str,
+    ) -> Result<StatementDescription, Box<dyn std::error::Error>> {
+        self.prepare_on_server("", query, &[])?;
+        let result = unsafe { PQdescribePrepared((&self.conn).into(), c"".as_ptr()) };
+        self.read_description(result)
+    }
+
+    /// Describes a named statement, preparing it on this connection first if needed.
+    pub(crate) fn describe_prepared(
+        &self,
+        statement: &Statement,
+    ) -> Result<StatementDescription, Box<dyn std::error::Error>> {
+        self.prepare(statement)?;
+        let c_name = CString::new(statement.name())?;
+        let result = unsafe { PQdescribePrepared((&self.conn).into(), c_name.as_ptr()) };
+        self.read_description(result)
+    }
+
+    /// Describes an open portal, such as a cursor declared in the current transaction.
+    pub(crate) fn describe_portal(
+        &self,
+        portal: &str,
+    ) -> Result<StatementDescription, Box<dyn std::error::Error>> {
+        let c_portal = CString::new(portal)?;
+        let result = unsafe { PQdescribePortal((&self.conn).into(), c_portal.as_ptr()) };
+        self.read_description(result)
+    }
+
+    // Reads the parameter and column metadata of a describe result and frees it
+    fn read_description(
+        &self,
+        result: *mut libpq_sys::PGresult,
+    ) -> Result<StatementDescription, Box<dyn std::error::Error>> {
+        if result.is_null() {
+            return Err(self.send_error().into());
+        }
+        if unsafe { PQresultStatus(result) } != PGRES_COMMAND_OK {
+            let error = self.result_error(result);
+            clear_pg_result(result);
+            return Err(error.into());
+        }
+
+        let param_types = (0..unsafe { PQnparams(result) })
+            .map(|index| unsafe { PQparamtype(result, index) })
+            .collect();
+
+        let columns = (0..unsafe { PQnfields(result) })
+            .map(|index| unsafe {
+                let name_ptr = PQfname(result, index);
+                let name = if name_ptr.is_null() {
+                    String::from("(unknown)")
+                } else {
+                    CStr::from_ptr(name_ptr).to_string_lossy().into_owned()
+                };
+                let table_oid = PQftable(result, index);
+                let table_column = PQftablecol(result, index);
+                ColumnDescription {
+                    name,
+                    type_oid: PQftype(result, index),
+                    type_modifier: PQfmod(result, index),
+                    type_size: PQfsize(result, index),
+                    table_oid: (table_oid != 0).then_some(table_oid),
+                    table_column: (table_column != 0).then_some(table_column),
+                }
+            })
+            .collect();
+
+        clear_pg_result(result);
+        Ok(StatementDescription {
+            param_types,
+            columns,
+        })
+    }
+
+    /// Sends a query whose rows are read with [`Session::next_rows`] as they
+    /// arrive, in chunked-rows mode with libpq 17 and single-row mode otherwise.
+    pub(crate) fn start_row_stream(&self, query: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.clear_notices();
+        let c_query = CString::new(query)?;
+
+        // The extended protocol limits the query to a single statement
+        debug!("Sending streamed query: {}", query);
+        let send_success = unsafe {
+            PQsendQueryParams(
+                (&self.conn).into(),
+                c_query.as_ptr(),
+                0,
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                0,
+            )
+        };
+        if send_success == 0 {
+            return Err(self.send_error().into());
+        }
+
+        let mode_set = match chunked_rows_mode() {
+            Some(set_chunked_rows_mode) => {
+                debug!("Streaming rows in chunks of {}", ROW_CHUNK_SIZE);
+                unsafe { set_chunked_rows_mode((&self.conn).into(), ROW_CHUNK_SIZE) }
+            }
+            None => {
+                debug!("Streaming rows in single-row mode");
+                unsafe { PQsetSingleRowMode((&self.conn).into()) }
+            }
+        };
+        if mode_set == 0 {
+            debug!("Could not enable row streaming, rows arrive in one result");
+        }
+        Ok(())
+    }
+
+    /// Reads the next rows of a streamed query, or `None` once the query is complete.
+    /// The final result is returned even if it holds no rows, for its column names.
+    ///
+    /// With a `timeout`, the query is cancelled if no rows arrive in time.
+    pub(crate) fn next_rows(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Rows>, Box<dyn std::error::Error>> {
+        let wait_start = Instant::now();
+        if let Some(timeout) = timeout {
+            if !self.wait_until_ready(wait_start + timeout) {
+                return Err(self.cancel_timed_out_query(wait_start).into());
+            }
+        }
+
+        let result = unsafe { PQgetResult((&self.conn).into()) };
+        if result.is_null() {
+            return Ok(None);
+        }
+
+        match unsafe { PQresultStatus_raw(result) } {
+            RAW_SINGLE_TUPLE | RAW_TUPLES_CHUNK => {
+                let rows = read_rows(result);
+                clear_pg_result(result);
+                Ok(Some(rows))
+            }
+            RAW_TUPLES_OK | RAW_COMMAND_OK => {
+                // The final result, holding any rows that were not streamed and the
+                // column names of a query that returned none
+                let rows = read_rows(result);
+                clear_pg_result(result);
+                self.consume_pending_results();
+                Ok(Some(rows))
+            }
+            _ => {
+                let error = self.result_error(result);
+                clear_pg_result(result);
+                self.consume_pending_results();
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Stops a streamed query whose rows are no longer wanted, so the connection
+    /// can be used again.
+    pub(crate) fn abandon_row_stream(&self) {
+        unsafe {
+            if PQconsumeInput((&self.conn).into()) != 0 && PQisBusy((&self.conn).into()) == 0 {
+                // Check whether the query already completed before cancelling it
+                let result = PQgetResult((&self.conn).into());
+                if result.is_null() {
+                    return;
+                }
+                clear_pg_result(result);
+            }
+        }
+
+        debug!("Cancelling streamed query that was not read to the end");
+        if let Some(handle) = &self.cancel {
+            if let Err(e) = handle.cancel() {
+                debug!("Cancel request failed: {}", e);
+            }
+        }
+
+        // Discard the rows still in flight until the server stops the query
+        let deadline = Instant::now() + CANCEL_GRACE;
+        loop {
+            if !self.wait_until_ready(deadline) {
+                debug!("Server did not stop the streamed query, discarding the connection");
+                self.broken.store(true, Ordering::Relaxed);
+                return;
+            }
+            let result = unsafe { PQgetResult((&self.conn).into()) };
+            if result.is_null() {
+                return;
+            }
+            clear_pg_result(result);
+        }
+    }
+
+    /// Runs the statements of a pipeline in libpq pipeline mode and returns one
+    /// result per statement, in order.
+    ///
+    /// A pipeline that times out leaves the connection in an unknown state, so the
+    /// connection is discarded.
+    pub(crate) fn execute_pipeline(
+        &self,
+        pipeline: &Pipeline,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<PipelineResult>, Box<dyn std::error::Error>> {
+        if pipeline.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.clear_notices();
+        let start_time = Instant::now();
+        let deadline = timeout.map(|timeout| start_time + timeout);
+
+        // Convert every statement up front so a bad value fails before anything is sent
+        let mut statements = Vec::with_capacity(pipeline.len());
+        for statement in &pipeline.statements {
+            let values = statement
+                .values
+                .iter()
+                .map(|value| value.as_deref().map(CString::new).transpose())
+                .collect::<Result<Vec<_>, _>>()?;
+            statements.push((CString::new(statement.query.as_str())?, values));
+        }
+
+        let conn = (&self.conn).into();
+        if unsafe { PQenterPipelineMode(conn) } == 0 {
+            return Err(self.send_error().into());
+        }
+        // Non-blocking sends let results be read while a large batch is still being
+        // written, so neither side stalls on a full buffer
+        unsafe { PQsetnonblocking(conn, 1) };
+
+        let outcome = self
+            .send_pipeline(pipeline, &statements, deadline, start_time)
+            .and_then(|()| self.read_pipeline(pipeline, deadline, start_time));
+
+        match outcome {
+            Ok(results) => {
+                unsafe {
+                    PQexitPipelineMode(conn);
+                    PQsetnonblocking(conn, 0);
+                }
+                Ok(results)
+            }
+            Err(e) => {
+                if e.is::<QueryTimeout>() {
+                    debug!("Pipeline timed out, discarding the connection");
+                    self.broken.store(true, Ordering::Relaxed);
+                } else if unsafe { PQexitPipelineMode(conn) } == 0 {
+                    debug!("Could not leave pipeline mode, discarding the connection");
+                    self.broken.store(true, Ordering::Relaxed);
+                }
+                unsafe { PQsetnonblocking(conn, 0) };
+                Err(e)
+            }
+        }
+    }
+
+    // Queues every statement of a pipeline with its sync points and sends them
+    fn send_pipeline(
+        &self,
+        pipeline: &Pipeline,
+        statements: &[(CString, Vec<Option<CString>>)],
+        deadline: Option<Instant>,
+        start_time: Instant,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = (&self.conn).into();
+        debug!("Sending pipeline of {} statements", statements.len());
+        for (index, (query, values)) in statements.iter().enumerate() {
+            let types = &pipeline.statements[index].types;
+            let value_ptrs = value_pointers(values);
+            let sent = unsafe {
+                PQsendQueryParams(
+                    conn,
+                    query.as_ptr(),
+                    values.len() as i32,
+                    if types.is_empty() {
+                        ptr::null()
+                    } else {
+                        types.as_ptr()
+                    },
+                    value_ptrs.as_ptr(),
+                    ptr::null(),
+                    ptr::null(),
+                    0,
+                )
+            };
+            if sent == 0 {
+                return Err(self.send_error().into());
+            }
+            let last = index + 1 == statements.len();
+            if (pipeline.isolate_statements || last) && unsafe { PQpipelineSync(conn) } == 0 {
+                return Err(self.send_error().into());
+            }
+        }
+
+        // Flush the queued statements, reading results meanwhile
+        loop {
+            match unsafe { PQflush(conn) } {
+                0 => return Ok(()),
+                1 => {}
+                _ => return Err(self.send_error().into()),
+            }
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(self.cancel_timed_out_query(start_time).into());
+                    }
+                    Some(deadline - now)
+                }
+                None => None,
+            };
+            wait_readable_or_writable(&self.conn, remaining);
+            if unsafe { PQconsumeInput(conn) } == 0 {
+                return Err(self.send_error().into());
+            }
+        }
+    }
+
+    // Reads the results of a sent pipeline, one per statement
+    fn read_pipeline(
+        &self,
+        pipeline: &Pipeline,
+        deadline: Option<Instant>,
+        start_time: Instant,
+    ) -> Result<Vec<PipelineResult>, Box<dyn std::error::Error>> {
+        let mut results = Vec::with_capacity(pipeline.len());
+        let mut statement_start = start_time;
+        for index in 0..pipeline.len() {
+            let mut outcome = None;
+            // Each statement's results end with a NULL result
+            loop {
+                let result = self.next_pipeline_result(deadline, start_time)?;
+                if result.is_null() {
+                    break;
+                }
+                let status = unsafe { PQresultStatus(result) };
+                if outcome.is_none() {
+                    outcome = Some(if status == PGRES_TUPLES_OK || status == PGRES_COMMAND_OK {
+                        PipelineResult::Completed(self.statement_result(result, statement_start))
+                    } else if status == PGRES_PIPELINE_ABORTED {
+                        PipelineResult::Aborted
+                    } else {
+                        PipelineResult::Failed(self.result_error(result))
+                    });
+                }
+                clear_pg_result(result);
+            }
+            let outcome =
+                outcome.ok_or("Pipeline ended before every statement returned a result")?;
+            debug!("Pipeline statement {}: {:?}", index, outcome.is_completed());
+            results.push(outcome);
+            statement_start = Instant::now();
+
+            // Consume the marker of the sync point that followed the statement
+            let last = index + 1 == pipeline.len();
+            if pipeline.isolate_statements || last {
+                let result = self.next_pipeline_result(deadline, start_time)?;
+                let synced =
+                    !result.is_null() && unsafe { PQresultStatus(result) } == PGRES_PIPELINE_SYNC;
+                clear_pg_result(result);
+                if !synced {
+                    return Err("Pipeline is out of step with the server".into());
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    // Waits for and returns the next pipeline result, which may be NULL
+    fn next_pipeline_result(
+        &self,
+        deadline: Option<Instant>,
+        start_time: Instant,
+    ) -> Result<*mut libpq_sys::PGresult, Box<dyn std::error::Error>> {
+        if let Some(deadline) = deadline {
+            if !self.wait_until_ready(deadline) {
+                return Err(self.cancel_timed_out_query(start_time).into());
+            }
+        }
+        if unsafe { PQstatus((&self.conn).into()) } != CONNECTION_OK {
+            return Err(self.send_error().into());
+        }
+        Ok(unsafe { PQgetResult((&self.conn).into()) })
+    }
+
+    /// Sends a `COPY ... FROM STDIN` statement and waits until the server is ready
+    /// for the data.
+    ///
+    /// With a `timeout`, the statement is cancelled if the server is not ready in time.
+    pub(crate) fn start_copy_in(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.clear_notices();
+        let start_time = Instant::now();
+        let c_query = CString::new(query)?;
+
+        debug!("Sending COPY: {}", query);
+        let send_success = unsafe { PQsendQuery((&self.conn).into(), c_query.as_ptr()) };
+        if send_success == 0 {
+            return Err(self.send_error().into());
+        }
+
+        if let Some(timeout) = timeout {
+            if !self.wait_until_ready(start_time + timeout) {
+                return Err(self.cancel_timed_out_query(start_time).into());
+            }
+        }
+        let result = unsafe { PQgetResult((&self.conn).into()) };
+        if result.is_null() {
+            return Err("No result returned".into());
+        }
+
+        let status = unsafe { PQresultStatus(result) };
+        if status == PGRES_COPY_IN {
+            clear_pg_result(result);
+            return Ok(());
+        }
+
+        let error: Box<dyn std::error::Error> =
+            if status == PGRES_TUPLES_OK || status == PGRES_COMMAND_OK {
+                "Statement is not a COPY FROM STDIN".into()
+            } else {
+                self.result_error(result).into()
+            };
+        clear_pg_result(result);
+        self.consume_pending_results();
+        Err(error)
+    }
+
+    /// Sends data for a running `COPY FROM STDIN`.
+    pub(crate) fn put_copy_data(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        for chunk in data.chunks(c_int::MAX as usize) {
+            let sent = unsafe {
+                PQputCopyData(
+                    (&self.conn).into(),
+                    chunk.as_ptr() as *const c_char,
+                    chunk.len() as c_int,
+                )
+            };
+            if sent != 1 {
+                return Err(self.send_error().into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends a running `COPY FROM STDIN` and returns the number of rows loaded.
+    ///
+    /// With a `timeout`, the copy is cancelled if the server has not finished
+    /// loading the data in time.
+    pub(crate) fn end_copy_in(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let start_time = Instant::now();
+        if unsafe { PQputCopyEnd((&self.conn).into(), ptr::null()) } != 1 {
+            return Err(self.send_error().into());
+        }
+
+        if let Some(timeout) = timeout {
+            if !self.wait_until_ready(start_time + timeout) {
+                return Err(self.cancel_timed_out_query(start_time).into());
+            }
+        }
+        let result = unsafe { PQgetResult((&self.conn).into()) };
+        if result.is_null() {
+            return Err("No result returned".into());
+        }
+
+        let outcome = if unsafe { PQresultStatus(result) } == PGRES_COMMAND_OK {
+            let rows = unsafe { CStr::from_ptr(PQcmdTuples(result)) }
+                .to_string_lossy()
+                .parse()
+                .unwrap_or(0);
+            debug!("COPY loaded {} rows", rows);
+            Ok(rows)
+        } else {
+            Err(self.result_error(result).into())
+        };
+        clear_pg_result(result);
+        self.consume_pending_results();
+        outcome
+    }
+
+    /// Aborts a running `COPY FROM STDIN`, so that none of its data is loaded and
+    /// the connection can be used again.
+    pub(crate) fn abort_copy_in(&self, message: &str) {
+        debug!("Aborting COPY FROM STDIN: {}", message);
+        let c_message = CString::new(message.replace('\0', "")).unwrap_or_default();
+        if unsafe { PQputCopyEnd((&self.conn).into(), c_message.as_ptr()) } != 1 {
+            debug!("Could not abort COPY, discarding the connection");
+            self.broken.store(true, Ordering::Relaxed);
+            return;
+        }
+        // The server reports the abort as an error, which is discarded
+        self.consume_pending_results();
+    }
+
+    /// Sends a `COPY ... TO STDOUT` statement and waits for its output to start;
+    /// returns whether the output is in binary format.
+    ///
+    /// With a `timeout`, the statement is cancelled if no output starts in time.
+    pub(crate) fn start_copy_out(
+        &self,
+        query: &str,
+        timeout: Option<Duration>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        self.clear_notices();
+        let start_time = Instant::now();
+        let c_query = CString::new(query)?;
+
+        debug!("Sending COPY: {}", query);
+        let send_success = unsafe { PQsendQuery((&self.conn).into(), c_query.as_ptr()) };
+        if send_success == 0 {
+            return Err(self.send_error().into());
+        }
+
+        if let Some(timeout) = timeout {
+            if !self.wait_until_ready(start_time + timeout) {
+                return Err(self.cancel_timed_out_query(start_time).into());
+            }
+        }
+        let result = unsafe { PQgetResult((&self.conn).into()) };
+        if result.is_null() {
+            return Err("No result returned".into());
+        }
+
+        let status = unsafe { PQresultStatus(result) };
+        if status == PGRES_COPY_OUT {
+            let binary = unsafe { PQbinaryTuples(result) } == 1;
+            clear_pg_result(result);
+            return Ok(binary);
+        }
+
+        let error: Box<dyn std::error::Error> =
+            if status == PGRES_TUPLES_OK || status == PGRES_COMMAND_OK {
+                "Statement is not a COPY TO STDOUT".into()
+            } else {
+                self.result_error(result).into()
+            };
+        clear_pg_result(result);
+        self.consume_pending_results();
+        Err(error)
+    }
+
+    /// Reads the next row of a running `COPY TO STDOUT` into `buffer`; returns
+    /// `false` once all rows were read.
+    ///
+    /// With a `timeout`, the copy is cancelled if no row arrives in time.
+    pub(crate) fn read_copy_data(
+        &self,
+        buffer: &mut Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let wait_start = Instant::now();
+        let deadline = timeout.map(|timeout| wait_start + timeout);
+        loop {
+            let mut data = ptr::null_mut();
+            let len = unsafe { PQgetCopyData((&self.conn).into(), &mut data, 1) };
+            if len > 0 {
+                buffer.clear();
+                buffer.extend_from_slice(unsafe {
+                    std::slice::from_raw_parts(data as *const u8, len as usize)
+                });
+                unsafe { libpq_sys::PQfreemem(data as *mut c_void) };
+                return Ok(true);
+            }
+            match len {
+                -1 => return Ok(false),
+                0 => {}
+                _ => return Err(self.send_error().into()),
+            }
+
+            // No complete row buffered yet
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(self.cancel_timed_out_query(wait_start).into());
+                    }
+                    Some(deadline - now)
+                }
+                None => None,
+            };
+            wait_readable(&self.conn, remaining);
+            if unsafe { PQconsumeInput((&self.conn).into()) } == 0 {
+                return Err(self.send_error().into());
+            }
+        }
+    }
+
+    /// Reads the outcome of a `COPY TO STDOUT` whose rows were all read and returns
+    /// the number of rows copied.
+    pub(crate) fn end_copy_out(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let start_time = Instant::now();
+        if let Some(timeout) = timeout {
+            if !self.wait_until_ready(start_time + timeout) {
+                return Err(self.cancel_timed_out_query(start_time).into());
+            }
+        }
+        let result = unsafe { PQgetResult((&self.conn).into()) };
+        if result.is_null() {
+            return Err("No result returned".into());
+        }
+
+        let outcome = if unsafe { PQresultStatus(result) } == PGRES_COMMAND_OK {
+            let rows = unsafe { CStr::from_ptr(PQcmdTuples(result)) }
+                .to_string_lossy()
+                .parse()
+                .unwrap_or(0);
+            debug!("COPY exported {} rows", rows);
+            Ok(rows)
+        } else {
+            Err(self.result_error(result).into())
+        };
+        clear_pg_result(result);
+        self.consume_pending_results();
+        outcome
+    }
+
+    /// Runs a `COPY TO STDOUT` and writes its output to `writer` as it arrives;
+    /// returns the number of rows copied.
+    pub(crate) fn copy_out(
+        &self,
+        query: &str,
+        writer: &mut dyn Write,
+        timeout: Option<Duration>,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        self.start_copy_out(query, timeout)?;
+        let mut buffer = Vec::new();
+        while self.read_copy_data(&mut buffer, timeout)? {
+            if let Err(e) = writer.write_all(&buffer) {
+                self.abandon_copy_out();
+                return Err(e.into());
+            }
+        }
+        if let Err(e) = writer.flush() {
+            // Every row has been read, so the copy can still complete
+            let _ = self.end_copy_out(timeout);
+            return Err(e.into());
+        }
+        self.end_copy_out(timeout)
+    }
+
+    /// Stops a `COPY TO STDOUT` whose output is no longer wanted, so the connection
+    /// can be used again.
+    pub(crate) fn abandon_copy_out(&self) {
+        if self.stop_copy_out() {
+            self.consume_pending_results();
+        }
+    }
+
+    // Cancels a running `COPY TO STDOUT` and discards its output; returns whether
+    // the copy ended, otherwise the connection is discarded
+    fn stop_copy_out(&self) -> bool {
+        debug!("Cancelling COPY TO STDOUT that was not read to the end");
+        if let Some(handle) = &self.cancel {
+            if let Err(e) = handle.cancel() {
+                debug!("Cancel request failed: {}", e);
+            }
+        }
+
+        let deadline = Instant::now() + CANCEL_GRACE;
+        loop {
+            let mut data = ptr::null_mut();
+            let len = unsafe { PQgetCopyData((&self.conn).into(), &mut data, 1) };
+            if len > 0 {
+                unsafe { libpq_sys::PQfreemem(data as *mut c_void) };
+                continue;
+            }
+            if len == -1 {
+                return true;
+            }
+            let now = Instant::now();
+            if len < -1 || now >= deadline {
+                debug!("Server did not stop the COPY, discarding the connection");
+                self.broken.store(true, Ordering::Relaxed);
+                return false;
+            }
+            wait_readable(&self.conn, Some(deadline - now));
+            unsafe { PQconsumeInput((&self.conn).into()) };
+        }
+    }
+}
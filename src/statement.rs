@@ -0,0 +1,199 @@
+// src/statement.rs
+
+use std::collections::HashMap;
+
+use crate::params::Oid;
+
+/// A named prepared statement, returned by
+/// [`PgwireLite::prepare`](crate::PgwireLite::prepare).
+///
+/// Prepared statements belong to a connection on the server, so a `Statement` only
+/// records the name and SQL text. It is prepared again automatically on any
+/// connection that does not have it yet, such as after a reconnect or on another
+/// pooled connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    name: String,
+    sql: String,
+}
+
+impl Statement {
+    pub(crate) fn new(name: &str, sql: &str) -> Self {
+        Statement {
+            name: name.to_string(),
+            sql: sql.to_string(),
+        }
+    }
+
+    /// Returns the name of the prepared statement on the server.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the SQL text of the statement.
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+}
+
+// Prefix of the names generated for cached statements, which user statements may
+// not use so the two can never collide on a connection
+const RESERVED_PREFIX: &str = "__pgwire_lite_";
+
+/// Rejects statement names that could collide with those of cached statements.
+pub(crate) fn check_statement_name(name: &str) -> Result<(), String> {
+    if name.starts_with(RESERVED_PREFIX) {
+        return Err(format!(
+            "Prepared statement names starting with \"{}\" are reserved",
+            RESERVED_PREFIX
+        ));
+    }
+    Ok(())
+}
+
+// Cached statements are identified by their SQL text and parameter types
+type CacheKey = (String, Vec<Oid>);
+
+struct CacheEntry {
+    name: String,
+    last_used: u64,
+}
+
+/// The statements a connection prepared for queries with parameters, keyed by
+/// SQL text, evicting the least recently used once full.
+pub(crate) struct StatementCache {
+    capacity: usize,
+    clock: u64,
+    next_id: u64,
+    entries: HashMap<CacheKey, CacheEntry>,
+}
+
+impl StatementCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        StatementCache {
+            capacity,
+            clock: 0,
+            next_id: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Returns the name of the cached statement for `sql` and `types`, marking it
+    /// as recently used.
+    pub(crate) fn get(&mut self, sql: &str, types: &[Oid]) -> Option<String> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(&(sql.to_string(), types.to_vec()))?;
+        entry.last_used = self.clock;
+        Some(entry.name.clone())
+    }
+
+    /// Returns a name for a new statement.
+    pub(crate) fn next_name(&mut self) -> String {
+        self.next_id += 1;
+        format!("{}{}", RESERVED_PREFIX, self.next_id)
+    }
+
+    /// Records a newly prepared statement and returns the statement evicted to
+    /// make room for it, which the caller should deallocate. A cache with a
+    /// capacity of 0 records nothing.
+    pub(crate) fn insert(&mut self, sql: &str, types: &[Oid], name: String) -> Option<String> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut evicted = None;
+        if self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = oldest {
+                evicted = self.entries.remove(&key).map(|entry| entry.name);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(
+            (sql.to_string(), types.to_vec()),
+            CacheEntry {
+                name,
+                last_used: self.clock,
+            },
+        );
+        evicted
+    }
+
+    /// Forgets a statement that no longer exists or can no longer be used.
+    pub(crate) fn remove(&mut self, sql: &str, types: &[Oid]) {
+        self.entries.remove(&(sql.to_string(), types.to_vec()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INT4: Oid = 23;
+
+    fn cache_statement(cache: &mut StatementCache, sql: &str) -> (String, Option<String>) {
+        let name = cache.next_name();
+        let evicted = cache.insert(sql, &[INT4], name.clone());
+        (name, evicted)
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_statement() {
+        let mut cache = StatementCache::new(2);
+        let (first, _) = cache_statement(&mut cache, "SELECT $1");
+        let (second, _) = cache_statement(&mut cache, "SELECT $1 + 1");
+
+        // Using the first statement makes the second the least recently used
+        assert_eq!(cache.get("SELECT $1", &[INT4]), Some(first.clone()));
+        let (third, evicted) = cache_statement(&mut cache, "SELECT $1 + 2");
+        assert_eq!(evicted, Some(second));
+        assert_eq!(cache.get("SELECT $1 + 1", &[INT4]), None);
+
+        // Now the first statement is the least recently used
+        assert_eq!(cache.get("SELECT $1 + 2", &[INT4]), Some(third));
+        let (_, evicted) = cache_statement(&mut cache, "SELECT $1 + 3");
+        assert_eq!(evicted, Some(first));
+    }
+
+    #[test]
+    fn distinguishes_parameter_types() {
+        let mut cache = StatementCache::new(2);
+        let (name, _) = cache_statement(&mut cache, "SELECT $1");
+        assert_eq!(cache.get("SELECT $1", &[INT4]), Some(name));
+        assert_eq!(cache.get("SELECT $1", &[25]), None);
+        assert_eq!(cache.get("SELECT $1", &[]), None);
+    }
+
+    #[test]
+    fn removed_statements_are_forgotten() {
+        let mut cache = StatementCache::new(2);
+        cache_statement(&mut cache, "SELECT $1");
+        cache.remove("SELECT $1", &[INT4]);
+        assert_eq!(cache.get("SELECT $1", &[INT4]), None);
+    }
+
+    #[test]
+    fn zero_capacity_caches_nothing() {
+        let mut cache = StatementCache::new(0);
+        let (_, evicted) = cache_statement(&mut cache, "SELECT $1");
+        assert_eq!(evicted, None);
+        assert_eq!(cache.get("SELECT $1", &[INT4]), None);
+        let (_, evicted) = cache_statement(&mut cache, "SELECT $1 + 1");
+        assert_eq!(evicted, None);
+    }
+
+    #[test]
+    fn generated_names_are_unique_and_reserved() {
+        let mut cache = StatementCache::new(2);
+        let first = cache.next_name();
+        let second = cache.next_name();
+        assert_ne!(first, second);
+        assert!(check_statement_name(&first).is_err());
+        assert!(check_statement_name(&second).is_err());
+        assert!(check_statement_name("pgwire_lite_1").is_ok());
+        assert!(check_statement_name("instances_by_region").is_ok());
+    }
+}
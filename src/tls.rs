@@ -0,0 +1,884 @@
+// src/tls.rs
+
+use std::ffi::{c_ulong, CStr, CString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+#[cfg(unix)]
+use std::sync::OnceLock;
+
+use foreign_types::ForeignTypeRef;
+use libpq::Connection;
+use libpq_sys::{PQsslAttribute, PQsslInUse, PQsslStruct};
+use openssl::hash::MessageDigest;
+use openssl::ssl::SslRef;
+use openssl::x509::{X509NameRef, X509Ref};
+
+/// How TLS is negotiated and how the server certificate is checked.
+///
+/// Mirrors the libpq `sslmode` connection parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SslMode {
+    /// Only try a non-TLS connection.
+    Disable,
+    /// Try a non-TLS connection first, then TLS if that fails.
+    Allow,
+    /// Try TLS first, then a non-TLS connection if that fails.
+    Prefer,
+    /// Require TLS, without verifying the server certificate.
+    Require,
+    /// Require TLS and verify the server certificate against the root certificate.
+    VerifyCa,
+    /// Require TLS, verify the server certificate and check the host name matches it.
+    VerifyFull,
+}
+
+impl SslMode {
+    /// Returns the libpq `sslmode` value for this mode.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SslMode::Disable => "disable",
+            SslMode::Allow => "allow",
+            SslMode::Prefer => "prefer",
+            SslMode::Require => "require",
+            SslMode::VerifyCa => "verify-ca",
+            SslMode::VerifyFull => "verify-full",
+        }
+    }
+}
+
+impl fmt::Display for SslMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for SslMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(SslMode::Disable),
+            "allow" => Ok(SslMode::Allow),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            _ => Err(format!("invalid sslmode value: \"{}\"", s)),
+        }
+    }
+}
+
+/// A TLS protocol version, used to bound the versions libpq will negotiate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    /// TLS 1.0, named `TLSv1` by libpq.
+    Tls1_0,
+    /// TLS 1.1.
+    Tls1_1,
+    /// TLS 1.2, the lowest version libpq negotiates by default.
+    Tls1_2,
+    /// TLS 1.3.
+    Tls1_3,
+}
+
+impl TlsVersion {
+    /// Returns the libpq name of this version, e.g. `TLSv1.2`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TlsVersion::Tls1_0 => "TLSv1",
+            TlsVersion::Tls1_1 => "TLSv1.1",
+            TlsVersion::Tls1_2 => "TLSv1.2",
+            TlsVersion::Tls1_3 => "TLSv1.3",
+        }
+    }
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for TlsVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "TLSv1" => Ok(TlsVersion::Tls1_0),
+            "TLSv1.1" => Ok(TlsVersion::Tls1_1),
+            "TLSv1.2" => Ok(TlsVersion::Tls1_2),
+            "TLSv1.3" => Ok(TlsVersion::Tls1_3),
+            _ => Err(format!("invalid TLS protocol version: \"{}\"", s)),
+        }
+    }
+}
+
+/// TLS settings for a connection.
+///
+/// Certificate and key paths are passed to libpq as connection parameters, so
+/// each client can use its own certificates without setting the process-wide
+/// `PGSSLCERT`, `PGSSLKEY` and `PGSSLROOTCERT` environment variables. Settings
+/// left unset fall back to libpq's defaults.
+///
+/// # Example
+///
+/// ```
+/// use pgwire_lite::{ConnectionConfig, SslMode, TlsConfig, TlsVersion};
+///
+/// let tls = TlsConfig::new(SslMode::VerifyCa)
+///     .root_cert("/etc/stackql/server_cert.pem")
+///     .client_cert("/etc/stackql/client_cert.pem")
+///     .client_key("/etc/stackql/client_key.pem")
+///     .min_protocol_version(TlsVersion::Tls1_2);
+///
+/// let config = ConnectionConfig::new("127.0.0.1", 5444).tls(tls);
+/// assert!(config.to_conninfo().contains("sslmode='verify-ca'"));
+/// assert!(config.to_conninfo().contains("sslcert='/etc/stackql/client_cert.pem'"));
+/// ```
+#[derive(Clone)]
+pub struct TlsConfig {
+    mode: Option<SslMode>,
+    root_cert: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    key_password: Option<String>,
+    min_protocol_version: Option<TlsVersion>,
+    max_protocol_version: Option<TlsVersion>,
+    pinned_fingerprints: Vec<String>,
+}
+
+impl TlsConfig {
+    /// Creates TLS settings with the given mode and no certificates configured.
+    pub fn new(mode: SslMode) -> Self {
+        TlsConfig {
+            mode: Some(mode),
+            ..Self::unset()
+        }
+    }
+
+    // Settings without a TLS mode, leaving sslmode to libpq's default and PGSSLMODE
+    pub(crate) fn unset() -> Self {
+        TlsConfig {
+            mode: None,
+            root_cert: None,
+            client_cert: None,
+            client_key: None,
+            key_password: None,
+            min_protocol_version: None,
+            max_protocol_version: None,
+            pinned_fingerprints: Vec::new(),
+        }
+    }
+
+    /// Sets the TLS mode.
+    pub fn mode(mut self, mode: SslMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Sets the file containing the certificate authorities used to verify the server.
+    pub fn root_cert(mut self, path: impl AsRef<Path>) -> Self {
+        self.root_cert = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the client certificate presented to the server.
+    pub fn client_cert(mut self, path: impl AsRef<Path>) -> Self {
+        self.client_cert = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the private key for the client certificate.
+    pub fn client_key(mut self, path: impl AsRef<Path>) -> Self {
+        self.client_key = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the password used to decrypt the client key.
+    pub fn key_password(mut self, password: &str) -> Self {
+        self.key_password = Some(password.to_string());
+        self
+    }
+
+    /// Sets the lowest TLS protocol version to negotiate.
+    pub fn min_protocol_version(mut self, version: TlsVersion) -> Self {
+        self.min_protocol_version = Some(version);
+        self
+    }
+
+    /// Sets the highest TLS protocol version to negotiate.
+    pub fn max_protocol_version(mut self, version: TlsVersion) -> Self {
+        self.max_protocol_version = Some(version);
+        self
+    }
+
+    /// Pins the server certificate to a SHA-256 fingerprint.
+    ///
+    /// The fingerprint is 64 hex digits, optionally separated by colons as printed by
+    /// `openssl x509 -noout -fingerprint -sha256`. Call this more than once to accept
+    /// any of several certificates, e.g. during a rotation. Once a fingerprint is
+    /// pinned, connections whose server certificate matches none of them fail with
+    /// [`FingerprintMismatch`]. The certificate is read as described for
+    /// [`TlsInfo::peer_certificate`]; if it cannot be read, pinned connections fail
+    /// with [`CertificateUnavailable`] instead.
+    ///
+    /// Pinning is checked as soon as the TLS handshake completes, before any password
+    /// is sent, so it can replace CA validation for self-signed certificates when
+    /// combined with [`SslMode::Require`]. With several hosts, one whose certificate
+    /// does not match is skipped, and the mismatch is only reported if no other
+    /// host accepts the connection.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use pgwire_lite::{SslMode, TlsConfig};
+    ///
+    /// let tls = TlsConfig::new(SslMode::Require).pin_sha256(
+    ///     "5E:2B:8F:3C:0A:91:7D:44:C6:19:E2:5B:AF:03:68:D7:1E:92:4C:B0:7F:3A:E5:16:88:C2:0D:FB:61:49:A7:3E",
+    /// );
+    /// assert!(tls.validate().is_ok());
+    /// assert!(TlsConfig::new(SslMode::Require).pin_sha256("not-hex").validate().is_err());
+    /// ```
+    pub fn pin_sha256(mut self, fingerprint: &str) -> Self {
+        self.pinned_fingerprints.push(fingerprint.to_string());
+        self
+    }
+
+    /// Returns the TLS mode.
+    ///
+    /// Settings parsed from a connection string without `sslmode` leave the mode to
+    /// libpq, which uses [`SslMode::Prefer`] unless `PGSSLMODE` says otherwise; this
+    /// returns `Prefer` for them.
+    pub fn get_mode(&self) -> SslMode {
+        self.mode.unwrap_or(SslMode::Prefer)
+    }
+
+    /// Returns the root certificate path, if set.
+    pub fn get_root_cert(&self) -> Option<&Path> {
+        self.root_cert.as_deref()
+    }
+
+    /// Returns the client certificate path, if set.
+    pub fn get_client_cert(&self) -> Option<&Path> {
+        self.client_cert.as_deref()
+    }
+
+    /// Returns the client key path, if set.
+    pub fn get_client_key(&self) -> Option<&Path> {
+        self.client_key.as_deref()
+    }
+
+    /// Returns the lowest TLS protocol version to negotiate, if set.
+    pub fn get_min_protocol_version(&self) -> Option<TlsVersion> {
+        self.min_protocol_version
+    }
+
+    /// Returns the highest TLS protocol version to negotiate, if set.
+    pub fn get_max_protocol_version(&self) -> Option<TlsVersion> {
+        self.max_protocol_version
+    }
+
+    /// Returns the pinned server certificate fingerprints, as passed to [`TlsConfig::pin_sha256`].
+    pub fn get_pinned_fingerprints(&self) -> &[String] {
+        &self.pinned_fingerprints
+    }
+
+    /// Checks that the settings are consistent.
+    ///
+    /// Returns an error if the protocol version range is empty, a client key
+    /// is configured without a client certificate (or vice versa), or a pinned
+    /// fingerprint is malformed or used with a mode that allows plaintext.
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(min), Some(max)) = (self.min_protocol_version, self.max_protocol_version) {
+            if min > max {
+                return Err(format!(
+                    "Invalid TLS protocol version range: minimum {} is above maximum {}",
+                    min, max
+                ));
+            }
+        }
+        if self.client_cert.is_some() != self.client_key.is_some() {
+            return Err(String::from(
+                "A TLS client certificate and client key must be configured together",
+            ));
+        }
+        if !self.pinned_fingerprints.is_empty() {
+            let mode = self.get_mode();
+            if matches!(mode, SslMode::Disable | SslMode::Allow | SslMode::Prefer) {
+                return Err(format!(
+                    "Certificate pinning requires an sslmode of require or stronger, not {}",
+                    mode
+                ));
+            }
+            for fingerprint in &self.pinned_fingerprints {
+                normalize_fingerprint(fingerprint)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the server certificate against the pinned fingerprints, if any.
+    ///
+    /// Fails with [`FingerprintMismatch`] if the certificate matches none of them, or
+    /// with [`CertificateUnavailable`] if there is no certificate to check.
+    pub(crate) fn check_pins(
+        &self,
+        info: Option<&TlsInfo>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.pinned_fingerprints.is_empty() {
+            return Ok(());
+        }
+        let Some(info) = info else {
+            return Err(Box::new(CertificateUnavailable {
+                reason: "the connection does not use TLS".to_string(),
+            }));
+        };
+        let Some(cert) = &info.peer_certificate else {
+            let reason = if openssl_abi_matches() {
+                "the server presented no certificate"
+            } else {
+                "the OpenSSL used by libpq cannot be read by the openssl crate"
+            };
+            return Err(Box::new(CertificateUnavailable {
+                reason: reason.to_string(),
+            }));
+        };
+
+        let expected: Vec<String> = self
+            .pinned_fingerprints
+            .iter()
+            .filter_map(|fingerprint| normalize_fingerprint(fingerprint).ok())
+            .collect();
+        if expected.contains(&cert.sha256_fingerprint) {
+            Ok(())
+        } else {
+            Err(Box::new(FingerprintMismatch {
+                expected,
+                actual: cert.sha256_fingerprint.clone(),
+            }))
+        }
+    }
+
+    // Keyword/value pairs passed to libpq for these settings
+    pub(crate) fn conninfo_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = Vec::new();
+        if let Some(mode) = self.mode {
+            pairs.push(("sslmode", mode.as_str().to_string()));
+        }
+        if let Some(path) = &self.root_cert {
+            pairs.push(("sslrootcert", path.to_string_lossy().into_owned()));
+        }
+        if let Some(path) = &self.client_cert {
+            pairs.push(("sslcert", path.to_string_lossy().into_owned()));
+        }
+        if let Some(path) = &self.client_key {
+            pairs.push(("sslkey", path.to_string_lossy().into_owned()));
+        }
+        if let Some(password) = &self.key_password {
+            pairs.push(("sslpassword", password.clone()));
+        }
+        if let Some(version) = self.min_protocol_version {
+            pairs.push(("ssl_min_protocol_version", version.as_str().to_string()));
+        }
+        if let Some(version) = self.max_protocol_version {
+            pairs.push(("ssl_max_protocol_version", version.as_str().to_string()));
+        }
+        pairs
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::new(SslMode::Disable)
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("mode", &self.mode)
+            .field("root_cert", &self.root_cert)
+            .field("client_cert", &self.client_cert)
+            .field("client_key", &self.client_key)
+            .field(
+                "key_password",
+                &self.key_password.as_ref().map(|_| "********"),
+            )
+            .field("min_protocol_version", &self.min_protocol_version)
+            .field("max_protocol_version", &self.max_protocol_version)
+            .field("pinned_fingerprints", &self.pinned_fingerprints)
+            .finish()
+    }
+}
+
+/// Details of the TLS session negotiated for a connection.
+///
+/// Reported by libpq after the handshake, so the values reflect what was
+/// actually agreed with the server rather than what was requested.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// The TLS library in use, e.g. `OpenSSL`.
+    pub library: Option<String>,
+    /// The negotiated protocol version, e.g. `TLSv1.3`.
+    pub protocol: Option<String>,
+    /// The negotiated cipher suite, e.g. `TLS_AES_256_GCM_SHA384`.
+    pub cipher: Option<String>,
+    /// The number of key bits used by the cipher.
+    pub key_bits: Option<u32>,
+    /// Whether TLS compression is in use.
+    pub compression: bool,
+    /// The certificate presented by the server, if one was received.
+    ///
+    /// Read from libpq's OpenSSL session, so it is only available on Unix when
+    /// libpq and the `openssl` crate use OpenSSL versions with the same ABI: any
+    /// 3.x release matches any other, while 1.x releases must share their minor
+    /// version. The OpenSSL libpq uses is looked up in libpq's own library. If it
+    /// cannot be found or does not match, this is `None` even though the server
+    /// sent a certificate, and connections with pinned fingerprints fail with
+    /// [`CertificateUnavailable`].
+    pub peer_certificate: Option<CertificateInfo>,
+}
+
+/// Identifying details of an X.509 certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateInfo {
+    /// The certificate subject, e.g. `CN=localhost, O=StackQL`.
+    pub subject: String,
+    /// The certificate issuer.
+    pub issuer: String,
+    /// Start of the validity period, e.g. `Oct 17 09:00:00 2026 GMT`.
+    pub not_before: String,
+    /// End of the validity period.
+    pub not_after: String,
+    /// SHA-256 fingerprint of the DER-encoded certificate, as colon separated
+    /// upper case hex (the format accepted by [`TlsConfig::pin_sha256`]).
+    pub sha256_fingerprint: String,
+}
+
+impl CertificateInfo {
+    fn from_x509(cert: &X509Ref) -> Self {
+        CertificateInfo {
+            subject: format_name(cert.subject_name()),
+            issuer: format_name(cert.issuer_name()),
+            not_before: cert.not_before().to_string(),
+            not_after: cert.not_after().to_string(),
+            sha256_fingerprint: cert
+                .digest(MessageDigest::sha256())
+                .map(|digest| format_fingerprint(&digest))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+// Formats a digest as colon separated upper case hex
+fn format_fingerprint(digest: &[u8]) -> String {
+    digest
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+// Parses a SHA-256 fingerprint written with or without colons into the canonical format
+fn normalize_fingerprint(fingerprint: &str) -> Result<String, String> {
+    let hex: String = fingerprint.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "Invalid SHA-256 certificate fingerprint: \"{}\"",
+            fingerprint
+        ));
+    }
+    let bytes: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or_default())
+        .collect();
+    Ok(format_fingerprint(&bytes))
+}
+
+/// Error returned when the server certificate does not match any pinned fingerprint.
+///
+/// Returned (boxed) when connecting with a [`TlsConfig`] that has pinned fingerprints;
+/// use `downcast_ref::<FingerprintMismatch>()` to tell it apart from other connection errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FingerprintMismatch {
+    /// The pinned fingerprints, in canonical format.
+    pub expected: Vec<String>,
+    /// The fingerprint of the certificate the server presented.
+    pub actual: String,
+}
+
+impl fmt::Display for FingerprintMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "server certificate fingerprint {} does not match any pinned fingerprint",
+            self.actual
+        )
+    }
+}
+
+impl std::error::Error for FingerprintMismatch {}
+
+/// Error returned when fingerprints are pinned but the server certificate cannot
+/// be checked against them.
+///
+/// This happens when the server presents no certificate, or when it cannot be
+/// read as described for [`TlsInfo::peer_certificate`]. Unlike
+/// [`FingerprintMismatch`] it does not mean the server is not the expected one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateUnavailable {
+    /// Why the certificate could not be checked.
+    pub reason: String,
+}
+
+impl fmt::Display for CertificateUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "server certificate cannot be checked against the pinned fingerprints: {}",
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for CertificateUnavailable {}
+
+// Formats a distinguished name as comma separated `KEY=value` entries
+fn format_name(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            let value = String::from_utf8_lossy(entry.data().as_slice());
+            format!("{}={}", key, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Reads a libpq SSL attribute, which is NULL when the attribute is not available
+fn ssl_attribute(conn: &Connection, name: &str) -> Option<String> {
+    let name = CString::new(name).ok()?;
+    unsafe {
+        let value = PQsslAttribute(conn.into(), name.as_ptr());
+        if value.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(value).to_string_lossy().into_owned())
+        }
+    }
+}
+
+/// Returns the negotiated TLS session details, or `None` if the connection is not using TLS.
+pub(crate) fn tls_info(conn: &Connection) -> Option<TlsInfo> {
+    if unsafe { PQsslInUse(conn.into()) } == 0 {
+        return None;
+    }
+
+    // The server certificate is only reachable through the OpenSSL session object
+    let peer_certificate = unsafe {
+        let ssl = PQsslStruct(conn.into(), c"OpenSSL".as_ptr()) as *mut openssl_sys::SSL;
+        if ssl.is_null() || !openssl_abi_matches() {
+            None
+        } else {
+            SslRef::from_ptr(ssl)
+                .peer_certificate()
+                .map(|cert| CertificateInfo::from_x509(&cert))
+        }
+    };
+
+    Some(TlsInfo {
+        library: ssl_attribute(conn, "library"),
+        protocol: ssl_attribute(conn, "protocol"),
+        cipher: ssl_attribute(conn, "cipher"),
+        key_bits: ssl_attribute(conn, "key_bits").and_then(|bits| bits.parse().ok()),
+        compression: ssl_attribute(conn, "compression").is_some_and(|c| c == "on"),
+        peer_certificate,
+    })
+}
+
+#[cfg(unix)]
+type OpenSslVersionNum = unsafe extern "C" fn() -> c_ulong;
+
+// The session object from PQsslStruct belongs to the OpenSSL libpq uses, while the
+// openssl crate calls the OpenSSL it was built against. Reading the object is only
+// sound if both share an ABI, so their versions are compared once. libpq's OpenSSL
+// is found by looking the symbol up in libpq's own dependency scope, so a libpq
+// linked against a different libssl than the rest of the process is detected.
+#[cfg(unix)]
+fn openssl_abi_matches() -> bool {
+    static MATCHES: OnceLock<bool> = OnceLock::new();
+    *MATCHES.get_or_init(|| {
+        let crate_version = unsafe { openssl_sys::OpenSSL_version_num() };
+        let Some(libpq_version) = (unsafe { libpq_openssl_version() }) else {
+            log::debug!(
+                "Could not find the OpenSSL used by libpq, server certificates cannot be read"
+            );
+            return false;
+        };
+        let matches = openssl_abi(crate_version) == openssl_abi(libpq_version);
+        if !matches {
+            log::debug!(
+                "OpenSSL {:#x} used by libpq does not match {:#x} used by the openssl crate, \
+                 server certificates cannot be read",
+                libpq_version,
+                crate_version
+            );
+        }
+        matches
+    })
+}
+
+// Calls OpenSSL_version_num as resolved from the library that contains libpq. A
+// handle from dlopen searches that library and its dependencies, not the whole
+// process. When libpq is linked into the program itself, the program's scope is
+// the one libpq's references resolve in.
+#[cfg(unix)]
+unsafe fn libpq_openssl_version() -> Option<c_ulong> {
+    let mut info: libc::Dl_info = std::mem::zeroed();
+    let libpq_symbol = PQsslStruct as *const libc::c_void;
+    if libc::dladdr(libpq_symbol, &mut info) == 0 || info.dli_fname.is_null() {
+        return None;
+    }
+    let mut handle = libc::dlopen(info.dli_fname, libc::RTLD_LAZY | libc::RTLD_NOLOAD);
+    if handle.is_null() {
+        handle = libc::dlopen(std::ptr::null(), libc::RTLD_LAZY);
+    }
+    if handle.is_null() {
+        return None;
+    }
+    let symbol = libc::dlsym(handle, c"OpenSSL_version_num".as_ptr());
+    let version = (!symbol.is_null())
+        .then(|| std::mem::transmute::<*mut libc::c_void, OpenSslVersionNum>(symbol)());
+    libc::dlclose(handle);
+    version
+}
+
+#[cfg(not(unix))]
+fn openssl_abi_matches() -> bool {
+    false
+}
+
+// The ABI series of an OpenSSL version number (0xMNNFFPPS): OpenSSL 3 keeps one ABI
+// across minor versions, while 1.x changed it with every minor version
+fn openssl_abi(version: c_ulong) -> c_ulong {
+    let major = version >> 28;
+    if major >= 3 {
+        major
+    } else {
+        version >> 20
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConnectionConfig;
+
+    const FINGERPRINT: &str =
+        "5E:2B:8F:3C:0A:91:7D:44:C6:19:E2:5B:AF:03:68:D7:1E:92:4C:B0:7F:3A:E5:16:88:C2:0D:FB:61:49:A7:3E";
+
+    fn info_with_fingerprint(fingerprint: Option<&str>) -> TlsInfo {
+        TlsInfo {
+            library: Some("OpenSSL".to_string()),
+            protocol: Some("TLSv1.3".to_string()),
+            cipher: None,
+            key_bits: None,
+            compression: false,
+            peer_certificate: fingerprint.map(|fingerprint| CertificateInfo {
+                subject: "CN=localhost".to_string(),
+                issuer: "CN=localhost".to_string(),
+                not_before: String::new(),
+                not_after: String::new(),
+                sha256_fingerprint: fingerprint.to_string(),
+            }),
+        }
+    }
+
+    #[test]
+    fn formats_fingerprints_as_colon_separated_upper_hex() {
+        assert_eq!(format_fingerprint(&[0x0a, 0xff, 0x00]), "0A:FF:00");
+        assert_eq!(format_fingerprint(&[]), "");
+    }
+
+    #[test]
+    fn normalizes_fingerprints_with_or_without_colons() {
+        let bare = FINGERPRINT.replace(':', "").to_lowercase();
+        assert_eq!(normalize_fingerprint(&bare).unwrap(), FINGERPRINT);
+        assert_eq!(normalize_fingerprint(FINGERPRINT).unwrap(), FINGERPRINT);
+
+        assert!(normalize_fingerprint("not-hex").is_err());
+        assert!(normalize_fingerprint(&bare[..62]).is_err());
+        assert!(normalize_fingerprint(&format!("{}zz", &bare[..62])).is_err());
+    }
+
+    #[test]
+    fn pins_accept_any_matching_fingerprint() {
+        let other = FINGERPRINT.replace("5E", "00");
+        let tls = TlsConfig::new(SslMode::Require)
+            .pin_sha256(&other)
+            .pin_sha256(&FINGERPRINT.replace(':', ""));
+
+        assert!(tls
+            .check_pins(Some(&info_with_fingerprint(Some(FINGERPRINT))))
+            .is_ok());
+        assert!(tls
+            .check_pins(Some(&info_with_fingerprint(Some(&other))))
+            .is_ok());
+    }
+
+    #[test]
+    fn pins_reject_other_or_missing_certificates() {
+        let tls = TlsConfig::new(SslMode::Require).pin_sha256(FINGERPRINT);
+        let other = FINGERPRINT.replace("5E", "00");
+
+        let err = tls
+            .check_pins(Some(&info_with_fingerprint(Some(&other))))
+            .unwrap_err();
+        let mismatch = err.downcast_ref::<FingerprintMismatch>().unwrap();
+        assert_eq!(mismatch.expected, vec![FINGERPRINT.to_string()]);
+        assert_eq!(mismatch.actual, other);
+
+        let err = tls.check_pins(None).unwrap_err();
+        assert!(err.is::<CertificateUnavailable>());
+        let err = tls
+            .check_pins(Some(&info_with_fingerprint(None)))
+            .unwrap_err();
+        assert!(err.is::<CertificateUnavailable>());
+    }
+
+    #[test]
+    fn no_pins_accept_any_certificate() {
+        let tls = TlsConfig::new(SslMode::Require);
+        assert!(tls.check_pins(None).is_ok());
+        assert!(tls
+            .check_pins(Some(&info_with_fingerprint(Some(FINGERPRINT))))
+            .is_ok());
+    }
+
+    #[test]
+    fn ssl_modes_round_trip_through_their_libpq_names() {
+        let modes = [
+            (SslMode::Disable, "disable"),
+            (SslMode::Allow, "allow"),
+            (SslMode::Prefer, "prefer"),
+            (SslMode::Require, "require"),
+            (SslMode::VerifyCa, "verify-ca"),
+            (SslMode::VerifyFull, "verify-full"),
+        ];
+        for (mode, name) in modes {
+            assert_eq!(mode.as_str(), name);
+            assert_eq!(mode.to_string(), name);
+            assert_eq!(name.parse::<SslMode>(), Ok(mode));
+        }
+        assert!("verify_full".parse::<SslMode>().is_err());
+    }
+
+    #[test]
+    fn tls_versions_round_trip_through_their_libpq_names() {
+        let versions = [
+            (TlsVersion::Tls1_0, "TLSv1"),
+            (TlsVersion::Tls1_1, "TLSv1.1"),
+            (TlsVersion::Tls1_2, "TLSv1.2"),
+            (TlsVersion::Tls1_3, "TLSv1.3"),
+        ];
+        for (version, name) in versions {
+            assert_eq!(version.as_str(), name);
+            assert_eq!(version.to_string(), name);
+            assert_eq!(name.parse::<TlsVersion>(), Ok(version));
+        }
+        assert!("TLSv1.0".parse::<TlsVersion>().is_err());
+        assert!(TlsVersion::Tls1_2 < TlsVersion::Tls1_3);
+    }
+
+    #[test]
+    fn validate_accepts_consistent_settings() {
+        assert!(TlsConfig::default().validate().is_ok());
+        assert!(TlsConfig::new(SslMode::VerifyFull)
+            .client_cert("client.pem")
+            .client_key("client.key")
+            .min_protocol_version(TlsVersion::Tls1_2)
+            .max_protocol_version(TlsVersion::Tls1_2)
+            .pin_sha256(FINGERPRINT)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_inconsistent_settings() {
+        let err = TlsConfig::new(SslMode::Require)
+            .min_protocol_version(TlsVersion::Tls1_3)
+            .max_protocol_version(TlsVersion::Tls1_2)
+            .validate()
+            .unwrap_err();
+        assert!(err.contains("minimum TLSv1.3 is above maximum TLSv1.2"));
+
+        let err = TlsConfig::new(SslMode::Require)
+            .client_cert("client.pem")
+            .validate()
+            .unwrap_err();
+        assert!(err.contains("configured together"));
+        assert!(TlsConfig::new(SslMode::Require)
+            .client_key("client.key")
+            .validate()
+            .is_err());
+
+        let err = TlsConfig::new(SslMode::Prefer)
+            .pin_sha256(FINGERPRINT)
+            .validate()
+            .unwrap_err();
+        assert!(err.contains("not prefer"));
+
+        let err = TlsConfig::new(SslMode::Require)
+            .pin_sha256("AB:CD")
+            .validate()
+            .unwrap_err();
+        assert!(err.contains("Invalid SHA-256"));
+    }
+
+    #[test]
+    fn settings_are_rendered_as_libpq_parameters() {
+        let tls = TlsConfig::new(SslMode::VerifyCa)
+            .root_cert("/etc/stackql/ca.pem")
+            .client_cert("/etc/stackql/client.pem")
+            .client_key("/etc/stackql/client.key")
+            .key_password("secret")
+            .min_protocol_version(TlsVersion::Tls1_2)
+            .max_protocol_version(TlsVersion::Tls1_3);
+        assert_eq!(
+            tls.conninfo_pairs(),
+            [
+                ("sslmode", "verify-ca".to_string()),
+                ("sslrootcert", "/etc/stackql/ca.pem".to_string()),
+                ("sslcert", "/etc/stackql/client.pem".to_string()),
+                ("sslkey", "/etc/stackql/client.key".to_string()),
+                ("sslpassword", "secret".to_string()),
+                ("ssl_min_protocol_version", "TLSv1.2".to_string()),
+                ("ssl_max_protocol_version", "TLSv1.3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn default_settings_disable_tls() {
+        assert_eq!(
+            TlsConfig::default().conninfo_pairs(),
+            [("sslmode", "disable".to_string())]
+        );
+        assert!(TlsConfig::unset().conninfo_pairs().is_empty());
+        assert_eq!(TlsConfig::unset().get_mode(), SslMode::Prefer);
+    }
+
+    #[test]
+    fn use_tls_switches_the_mode_and_keeps_certificates() {
+        let config = ConnectionConfig::new("localhost", 5444)
+            .tls(TlsConfig::new(SslMode::Require).root_cert("ca.pem"))
+            .use_tls(true);
+        assert_eq!(config.get_tls().get_mode(), SslMode::VerifyFull);
+        assert_eq!(config.get_tls().get_root_cert(), Some(Path::new("ca.pem")));
+
+        let config = config.use_tls(false);
+        assert_eq!(config.get_tls().get_mode(), SslMode::Disable);
+        assert!(config.to_conninfo().contains("sslmode='disable'"));
+    }
+}
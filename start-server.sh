@@ -15,5 +15,8 @@ check_and_stop_server
 PGPORT=5444
 echo "starting local stackql server on port $PGPORT"
 nohup ./stackql srv --pgsrv.port=$PGPORT --pgsrv.debug.enable=true --pgsrv.loglevel=DEBUG > stackql_server.log 2>&1 &
-sleep 5
+if ! cargo run --quiet --example wait_for_server -- $PGPORT 30; then
+  echo "stackql server did not start, see stackql_server.log"
+  exit 1
+fi
 echo "stackql server started"
\ No newline at end of file
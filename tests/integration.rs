@@ -2,7 +2,10 @@
 
 use colorize::AnsiColor;
 use libpq_sys::ExecStatusType;
-use pgwire_lite::{PgwireLite, QueryResult, Value};
+use pgwire_lite::{
+    ConnectionConfig, PgwireLite, PingStatus, Pool, PoolConfig, QueryResult, StatementError, Value,
+};
+use std::time::Duration;
 
 fn print_heading(title: &str) {
     let title_owned = title.to_string(); // Convert &str to String
@@ -232,6 +235,22 @@ impl Default for QueryAssertions {
     }
 }
 
+#[test]
+fn test_ping() {
+    let conn =
+        PgwireLite::new("localhost", 5444, false, "verbose").expect("Failed to create client");
+    conn.wait_until_ready(Duration::from_secs(30), Duration::from_millis(100))
+        .expect("Server did not become ready");
+    assert_eq!(conn.ping(), PingStatus::Ok);
+
+    // Nothing listens on this port
+    let conn = PgwireLite::new("localhost", 1, false, "verbose").expect("Failed to create client");
+    assert_eq!(conn.ping(), PingStatus::NoResponse);
+    assert!(conn
+        .wait_until_ready(Duration::from_millis(300), Duration::from_millis(50))
+        .is_err());
+}
+
 #[test]
 fn test_queries() {
     // Create a single connection to be used for all queries
@@ -412,3 +431,118 @@ fn test_queries() {
 
     println!("All tests completed successfully!");
 }
+
+#[test]
+fn test_row_iter() {
+    let conn = PgwireLite::from_config(ConnectionConfig::new("localhost", 5444).persistent(true))
+        .expect("Failed to create client");
+
+    // Column names are reported even when there are no rows
+    let mut rows = conn
+        .query_iter("SELECT 1 as col_name WHERE 1=0")
+        .expect("Query failed");
+    assert!(rows.next().is_none());
+    assert_eq!(rows.column_names(), Some(&["col_name".to_string()][..]));
+    drop(rows);
+
+    // Other calls on the same thread fail instead of waiting for the iterator
+    let mut rows = conn
+        .query_iter("SELECT 1 as col_name UNION ALL SELECT 2 UNION ALL SELECT 3")
+        .expect("Query failed");
+    let err = conn
+        .query("SELECT 1 as col_name")
+        .expect_err("Query ran while rows were streaming");
+    assert!(err.to_string().contains("Session busy"), "{}", err);
+
+    // Dropping the iterator early stops the query and leaves the connection usable
+    let first = rows.next().expect("No rows").expect("Failed to read row");
+    assert_eq!(
+        first.get("col_name").map(|v| v.to_string()).as_deref(),
+        Some("1")
+    );
+    drop(rows);
+    let result = conn.query("SELECT 2 as col_name").expect("Query failed");
+    assert_eq!(result.row_count, 1);
+    assert!(conn.is_connected());
+}
+
+#[test]
+fn test_pooled_session_busy() {
+    let pool = Pool::new(
+        ConnectionConfig::new("localhost", 5444),
+        PoolConfig::new().max_size(1),
+    )
+    .expect("Failed to create pool");
+    let conn = pool.get().expect("No connection available");
+
+    let rows = conn
+        .query_iter("SELECT 1 as col_name")
+        .expect("Query failed");
+    let err = conn
+        .query("SELECT 2 as col_name")
+        .expect_err("Query ran while rows were streaming");
+    assert!(err.to_string().contains("Session busy"), "{}", err);
+    assert!(conn.copy_out_iter("COPY t TO STDOUT").is_err());
+
+    // The connection is usable again once the iterator is gone
+    drop(rows);
+    let result = conn.query("SELECT 2 as col_name").expect("Query failed");
+    assert_eq!(result.row_count, 1);
+}
+
+#[test]
+fn test_query_multi() {
+    let conn =
+        PgwireLite::new("localhost", 5444, false, "default").expect("Failed to create client");
+
+    let results = conn
+        .query_multi("SELECT 1 as a; SELECT 2 as b UNION ALL SELECT 3")
+        .expect("Query failed");
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].column_names, ["a".to_string()]);
+    assert_eq!(results[0].row_count, 1);
+    assert_eq!(results[1].column_names, ["b".to_string()]);
+    assert_eq!(results[1].row_count, 2);
+
+    // The error reports which statement failed and keeps the earlier results
+    let err = conn
+        .query_multi("SELECT 1 as a; SELECT * FROM nonexistent_table")
+        .expect_err("Query on a missing table succeeded");
+    let failed = err
+        .downcast_ref::<StatementError>()
+        .expect("Not a statement error");
+    assert_eq!(failed.index, 1);
+    assert_eq!(failed.results.len(), 1);
+    assert_eq!(failed.results[0].row_count, 1);
+}
+
+#[test]
+fn test_describe() {
+    let conn = PgwireLite::from_config(ConnectionConfig::new("localhost", 5444).persistent(true))
+        .expect("Failed to create client");
+
+    let description = conn
+        .describe("SELECT 1 as col_name, 'a' as other_col")
+        .expect("Describe failed");
+    assert!(description.param_types.is_empty());
+    let names: Vec<_> = description
+        .columns
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect();
+    assert_eq!(names, ["col_name", "other_col"]);
+    assert!(description.columns.iter().all(|c| c.table_oid.is_none()));
+
+    // Describing does not run the statement, and the connection stays usable
+    assert!(conn.describe("SELECT * FROM nonexistent_table").is_err());
+    let result = conn.query("SELECT 1 as col_name").expect("Query failed");
+    assert_eq!(result.row_count, 1);
+
+    let statement = conn
+        .prepare("describe_test", "SELECT $1::text as col_name")
+        .expect("Prepare failed");
+    let description = conn.describe_prepared(&statement).expect("Describe failed");
+    assert_eq!(description.param_types.len(), 1);
+    assert_eq!(description.columns.len(), 1);
+    assert_eq!(description.columns[0].name, "col_name");
+}
@@ -3,7 +3,10 @@
 
 use colorize::AnsiColor;
 use libpq_sys::ExecStatusType;
-use pgwire_lite::{PgwireLite, QueryResult, Value};
+use pgwire_lite::{
+    ConnectionConfig, FingerprintMismatch, PgwireLite, QueryResult, SslMode, TlsConfig, Value,
+    Verbosity,
+};
 use std::env;
 use std::path::PathBuf;
 
@@ -273,6 +276,64 @@ fn setup() -> PgwireLite {
         .expect("Failed to create TLS connection")
 }
 
+// Test a TLS connection configured per client, without TLS environment variables
+#[test]
+fn test_tls_config_connection() {
+    let home_dir = env::var("HOME").expect("Could not find HOME environment variable");
+    let ssl_dir = PathBuf::from(&home_dir).join("ssl-test");
+
+    let tls = TlsConfig::new(SslMode::VerifyCa)
+        .root_cert(ssl_dir.join("server_cert.pem"))
+        .client_cert(ssl_dir.join("client_cert.pem"))
+        .client_key(ssl_dir.join("client_key.pem"));
+    let config = ConnectionConfig::new(SERVER_HOST, SERVER_PORT)
+        .tls(tls)
+        .verbosity(Verbosity::Verbose);
+    let conn = PgwireLite::from_config(config).expect("Failed to create TLS client");
+
+    let passed = execute_query_with_assertions(
+        &conn,
+        "SELECT 1 as col_name",
+        QueryAssertions {
+            expected_status: Some(ExecStatusType::PGRES_TUPLES_OK),
+            expected_col_count: Some(1),
+            expected_values: Some(vec![("col_name".to_string(), "1".to_string())]),
+            ..Default::default()
+        },
+    );
+    assert!(passed, "Query over TLS configured with TlsConfig failed");
+
+    let tls = conn
+        .tls_info()
+        .expect("Failed to connect")
+        .expect("Connection is not using TLS");
+    assert!(tls.protocol.is_some(), "Negotiated protocol not reported");
+    assert!(tls.cipher.is_some(), "Negotiated cipher not reported");
+    assert!(
+        tls.peer_certificate.is_some(),
+        "Server certificate not reported"
+    );
+}
+
+// Test that a connection is refused when the server certificate is not the pinned one
+#[test]
+fn test_pinned_fingerprint_mismatch() {
+    let tls = TlsConfig::new(SslMode::Require).pin_sha256(&"00".repeat(32));
+    let config = ConnectionConfig::new(SERVER_HOST, SERVER_PORT).tls(tls);
+    let conn = PgwireLite::from_config(config).expect("Failed to create TLS client");
+
+    let err = conn
+        .query("SELECT 1 as col_name")
+        .expect_err("Connection with a wrong pinned fingerprint should fail");
+    let mismatch = err
+        .downcast_ref::<FingerprintMismatch>()
+        .expect("Expected a FingerprintMismatch error");
+    assert!(
+        !mismatch.actual.is_empty(),
+        "Server certificate not reported"
+    );
+}
+
 // Test for non-TLS connection to TLS server
 // We're expecting this to either fail OR succeed differently than a TLS connection
 #[test]